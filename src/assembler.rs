@@ -1,22 +1,80 @@
-use crate::vm::Immediate;
+use {
+    crate::{
+        expr::{eval, parse_int, ExprError}, lexer::{tokenize, unescape, Comment, Token}, program::{DebugInfo, LineEntry, Program, Symbol}, vm::{Immediate, Register, REGISTER_COUNT},
+    },
+    std::{collections::HashMap, fmt, fs, mem, path::{Path, PathBuf}},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmDiagnostic {
+    pub file: String,                    // empty for sources without a path, e.g. ones given to Assembler::new
+    pub line: usize,                     // 1 based
+    pub col: usize,                      // 1 based, in chars
+    pub snippet: String,                 // the source line the diagnostic points into
+    pub msg: String,
+}
+
+impl fmt::Display for AsmDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pad: String = self.snippet.chars().take(self.col-1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+        }
+
+        write!(f, "{}:{}: {}\n    {}\n    {pad}^", self.line, self.col, self.msg, self.snippet)
+    }
+}
+
+// a file to assemble, the path names it in diagnostics & is what its .includes are resolved relative to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    pub text: String,
+}
 
-/* TODO: labels
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub lines: Vec<String>,
+}
 
-HashMap<String, usize> under Assembler for labels & their bit position
-Vec<(usize, String)> under Assembler for bits to be replaced with labels' starting bit
+// data directives, a label defined right before one of these names the data's heap address instead of a bit position
+const DATA_DIRECTIVES: [&str; 11] = [
+    ".string", ".u8", ".i8", ".u16", ".i16", ".u32", ".i32", ".u64", ".i64", ".f32", ".f64",
+];
 
-*/
+// how deep macros may invoke other macros before it's assumed they recurse forever
+const MAX_MACRO_DEPTH: usize = 64;
 
-use std::collections::HashMap;
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    locals: Vec<String>,                 // labels defined in the body, renamed on every expansion so they don't collide
+    body: Vec<Token>,
+}
 
 pub struct Assembler {
     machine_c: Vec<u8>,
-    lbls: HashMap<String, usize>,
-    lbl_replaces: Vec<(usize, String)>,
-    src: Vec<char>,
-    bit: usize,
-    ch: char,
+    data: Vec<Immediate>,
+    lbls: HashMap<String, usize>,        // label name -> bit position (or heap address for data) it was defined at
+    lbl_replaces: Vec<(usize, String, Token)>, // bit position & int type of a zeroed placeholder -> expression to fill it with
+    data_replaces: Vec<(usize, String, Token)>, // same for values in the data segment
+    lbl_uses: Vec<(Token, bool)>,        // jump, call & heap address operands, & whether a label there should be a data label
+    consts: HashMap<String, Token>,      // .equ name -> expression
+    const_vals: HashMap<String, Result<i128, ExprError>>, // what every constant evaluated to, so it's only evaluated once
+    resolving: Vec<String>,              // the constants being evaluated, one in terms of the next
+    symbols: Vec<Symbol>,                // every label, in the order they were defined in
+    entry: Option<Token>,                // .entry expression, execution starts at 0 without one
+    debug_lines: Vec<LineEntry>,
+    files: Vec<SourceFile>,              // every file read, in the order they were read in, a token's file is an index
+    include_dirs: Vec<PathBuf>,          // searched in order for .includes not found next to the file including them
+    toks: Vec<Token>,
+    comments: Vec<Comment>,
     i: usize,
+    diags: Vec<AsmDiagnostic>,
+    macros: HashMap<String, Macro>,
+    expansions: Vec<(String, Token)>,    // macro name & invocation of every expansion, a token's expansion is an index + 1
 }
 
 pub enum Opcode {
@@ -47,619 +105,1078 @@ pub enum Opcode {
     HSTORER = 24,  // strR [reg]
     HLOAD = 25,    // ld [addr]
     HLOADR = 26,   // ldR [reg]
+    CALL = 27,     // call [lbl]
+    CALLR = 28,    // callR [reg]
+    RET = 29,      // ret
 } 
 
 impl Assembler {
     pub fn new(src: String) -> Self {
-        Self {
+        Self::from_sources(vec![Source { path: PathBuf::new(), text: src }], &[])
+    }
+
+    // assembles the sources one after another, sharing a single label namespace
+    pub fn from_sources(sources: Vec<Source>, include_dirs: &[PathBuf]) -> Self {
+        let mut assembler = Self {
             machine_c: vec![],
+            data: vec![],
             lbls: HashMap::new(),
             lbl_replaces: vec![],
-            src: src.chars().collect(),
-            ch: src.chars().nth(0).unwrap_or('\0'),
-            bit: 0,
+            data_replaces: vec![],
+            lbl_uses: vec![],
+            consts: HashMap::new(),
+            const_vals: HashMap::new(),
+            resolving: vec![],
+            symbols: vec![],
+            entry: None,
+            debug_lines: vec![],
+            files: vec![],
+            include_dirs: include_dirs.to_vec(),
+            toks: vec![],
+            comments: vec![],
             i: 0,
+            diags: vec![],
+            macros: HashMap::new(),
+            expansions: vec![],
+        };
+
+        let mut toks = vec![];
+        for source in sources {
+            let mut including = vec![(canonical(&source.path), source.path.clone())];
+            toks.extend(assembler.load(&source.path, &source.text, &mut including));
         }
+
+        assembler.toks = assembler.expand_macros(toks, 0);
+        assembler
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
     }
 
-    pub fn assemble(&mut self) -> Vec<u8> {
-        while self.ch != '\0' {
-            self.assemble_instr();
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn assemble(&mut self) -> Result<Program, Vec<AsmDiagnostic>> {
+        while self.i < self.toks.len() {
+            let start = self.toks[self.i].clone();
+
+            if let Err(diag) = self.assemble_instr() {
+                self.diags.push(diag);
+
+                // skip the rest of the line so that one mistake is only reported once
+                while self.i < self.toks.len() && self.toks[self.i].same_line(&start) {
+                    self.i += 1;
+                }
+            }
         }
 
-        for (i, name) in self.lbl_replaces.iter() {
-            let bit = match self.lbls.get(name) {
-                Some(bit) => *bit,
-                _ => panic!("unknown label {name:?}"),
-            };
+        for (i, int_type, tok) in mem::take(&mut self.lbl_replaces) {
+            match self.eval_typed(&tok, &int_type) {
+                Ok(immed) => {
+                    // the placeholder is only the value, without the immediate's type tag
+                    let encoded = encode_immed(immed);
+                    self.machine_c[i..][..encoded.len()-1].copy_from_slice(&encoded[1..]);
+                },
+                Err(e) => self.diags.push(self.diag(&tok, e.to_string())),
+            }
+        }
+
+        for (i, int_type, tok) in mem::take(&mut self.data_replaces) {
+            match self.eval_typed(&tok, &int_type) {
+                Ok(immed) => self.data[i] = immed,
+                Err(e) => self.diags.push(self.diag(&tok, e.to_string())),
+            }
+        }
 
-            self.machine_c.insert((*i)-1, bit as u8);
+        // a label is either an address in the code or one in the heap, using it as the other is a mistake
+        // (expressions are left alone, e.g. end-start is a length whichever kind the labels are)
+        for (tok, is_data) in mem::take(&mut self.lbl_uses) {
+            match self.symbols.iter().find(|sym| sym.name == tok.text) {
+                Some(sym) if sym.is_data && !is_data => {
+                    self.diags.push(self.diag(&tok, format!("{:?} is a data label, it can't be jumped to or called", tok.text)));
+                },
+                Some(sym) if !sym.is_data && is_data => {
+                    self.diags.push(self.diag(&tok, format!("{:?} is a code label, it can't be used as a heap address", tok.text)));
+                },
+                _ => {},
+            }
+        }
+
+        let entry = match self.entry.clone() {
+            Some(tok) => match self.eval_typed(&tok, "u32") {
+                Ok(Immediate::U32(entry)) if entry as usize > self.machine_c.len() => {
+                    self.diags.push(self.diag(&tok, format!("entry point {} is past the end of the code", tok.text)));
+                    0
+                },
+                // the end of the code is where the appended HLT goes
+                Ok(Immediate::U32(entry)) if entry as usize == self.machine_c.len() || self.debug_lines.iter().any(|l| l.addr == entry as usize) => {
+                    entry as usize
+                },
+                Ok(_) => {
+                    self.diags.push(self.diag(&tok, format!("entry point {} is in the middle of an instruction", tok.text)));
+                    0
+                },
+                Err(e) => {
+                    self.diags.push(self.diag(&tok, e.to_string()));
+                    0
+                },
+            },
+            _ => 0,
+        };
+
+        if !self.diags.is_empty() {
+            return Err(mem::take(&mut self.diags));
         }
 
         self.machine_c.push(1);
-        return self.machine_c.clone();
+        Ok(Program {
+            code: self.machine_c.clone(),
+            data: self.data.clone(),
+            entry,
+            symbols: self.symbols.clone(),
+            debug: DebugInfo {
+                files: self.files.iter().map(|f| f.name.clone()).collect(),
+                lines: self.debug_lines.clone(),
+            },
+        })
     }
 
-    fn assemble_instr(&mut self) {
-        if self.ch == '.' {
-            self.adv();
+    fn assemble_instr(&mut self) -> Result<(), AsmDiagnostic> {
+        let tok = self.toks[self.i].clone();
+        self.i += 1;
+
+        if let Some(name) = tok.text.strip_prefix('.') {
+            if DATA_DIRECTIVES.contains(&tok.text.as_str()) {
+                return self.data_directive(&tok);
+            }
+
+            if let "equ"|"define" = name {
+                return self.constant(&tok);
+            }
+
+            // .entry lbl -> execution starts at lbl instead of at the first instr
+            if name == "entry" {
+                if let Some(prev) = &self.entry {
+                    return Err(self.diag(&tok, format!("entry point is already set to {:?}", prev.text)));
+                }
+
+                self.entry = Some(self.operand(&tok, "expected label after .entry")?);
+                return Ok(());
+            }
+
+            // anything else couldn't be used in an expression, e.g. a-b is a minus b
+            if !is_ident(name) {
+                return Err(self.diag(&tok, format!("label names have to be identifiers, got {name:?}")));
+            }
 
-            let name = self.rd_til_ws();
-            self.lbls.insert(name, self.bit.clone());
+            if self.lbls.contains_key(name) || self.consts.contains_key(name) {
+                return Err(self.diag(&tok, format!("label {name:?} is already defined")));
+            }
 
-            return;
+            let is_data = match self.toks.get(self.i) {
+                Some(next) => DATA_DIRECTIVES.contains(&next.text.as_str()),
+                _ => false,
+            };
+
+            let addr = if is_data { self.data.len() } else { self.machine_c.len() };
+            self.lbls.insert(name.to_string(), addr);
+            self.forget_unresolved();
+            self.symbols.push(Symbol { name: name.to_string(), addr, is_data });
+            return Ok(());
         }
 
-        let opcode = self.rd_til_ws();
-        self.bit += 1;
-        
-        match opcode.as_str() {
+        // code produced by a macro is attributed to the invocation in the source, like diagnostics are
+        let site = self.site(&tok);
+        self.debug_lines.push(LineEntry { addr: self.machine_c.len(), file: site.file, line: site.line, col: site.col });
+
+        match tok.text.as_str() {
             "_" => self.machine_c.push(Opcode::NOP as u8),
             "hlt" => self.machine_c.push(Opcode::HLT as u8),
             "int" => {
                 self.machine_c.push(Opcode::INT as u8);
-
-                let int = match self.addr() {
-                    Some(n) => n as u8,
-                    _ => panic!("expected interrupt number after INT instr"),
-                };
-
-                self.machine_c.push(int);
+                self.addr(&tok, "expected interrupt number after INT instr")?;
             },
             "$" => {
                 self.machine_c.push(Opcode::PUSH as u8);
-                
-                let immed = match self.immed() {
-                    Some(i) => i,
-                    _ => panic!("expected immediate after PUSH instr"),
-                };
-
-                self.psh_encoded_immed(immed);
+                self.immed(&tok, "expected immediate after PUSH instr")?;
             },
             "$$" => {
                 self.machine_c.push(Opcode::PUSHR as u8);
-
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected register after PUSHR instr"),
-                } as u8;
-
-                self.machine_c.push(reg);
+                self.reg(&tok, "expected register after PUSHR instr")?;
             },
             "%" => {
                 self.machine_c.push(Opcode::POP as u8);
-
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected register after POP instr"),
-                } as u8;
-
-                self.machine_c.push(reg);
+                self.reg(&tok, "expected register after POP instr")?;
             },
             "@" => {
                 self.machine_c.push(Opcode::LDI as u8);
-
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected register after LDI instr"),
-                } as u8;
-
-                self.machine_c.push(reg);
-
-                let immed = match self.immed() {
-                    Some(i) => i,
-                    _ => panic!("expected immed after register after LDI instr"),
-                };
-
-                self.psh_encoded_immed(immed);
+                self.reg(&tok, "expected register after LDI instr")?;
+                self.immed(&tok, "expected immed after register after LDI instr")?;
             },
             ":" => {
                 self.machine_c.push(Opcode::CPY as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after CPY instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after CPY instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after CPY instr")?;
+                self.reg(&tok, "expected two registers after CPY instr")?;
             },
             "//" => {
                 self.machine_c.push(Opcode::JMP as u8);
-                self.lbl();
+                self.lbl(&tok, "expected label after JMP instr")?;
             },
             "/=" => {
                 self.machine_c.push(Opcode::JE as u8);
-                self.lbl();
+                self.lbl(&tok, "expected label after JE instr")?;
             },
             "/!" => {
                 self.machine_c.push(Opcode::JNE as u8);
-                self.lbl();
+                self.lbl(&tok, "expected label after JNE instr")?;
             },
             "/>" => {
                 self.machine_c.push(Opcode::JG as u8);
-                self.lbl();
+                self.lbl(&tok, "expected label after JG instr")?;
             },
             "/<" => {
                 self.machine_c.push(Opcode::JL as u8);
-                self.lbl();
+                self.lbl(&tok, "expected label after JL instr")?;
             },
             "//R" => {
                 self.machine_c.push(Opcode::JMP as u8);
-
-                let addr = match self.addr() {
-                    Some(a) => a,
-                    _ => panic!("expected addr after JMP (raw) instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.addr(&tok, "expected addr after JMP (raw) instr")?;
             },
             "/=R" => {
                 self.machine_c.push(Opcode::JE as u8);
-
-                let addr = match self.addr() {
-                    Some(a) => a,
-                    _ => panic!("expected addr after JE (raw) instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.addr(&tok, "expected addr after JE (raw) instr")?;
             },
             "/!R" => {
                 self.machine_c.push(Opcode::JNE as u8);
-
-                let addr = match self.addr() {
-                    Some(a) => a,
-                    _ => panic!("expected addr after JNE (raw) instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.addr(&tok, "expected addr after JNE (raw) instr")?;
             },
             "/>R" => {
                 self.machine_c.push(Opcode::JG as u8);
-
-                let addr = match self.addr() {
-                    Some(a) => a,
-                    _ => panic!("expected addr after JG (raw) instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.addr(&tok, "expected addr after JG (raw) instr")?;
             },
             "/<R" => {
                 self.machine_c.push(Opcode::JL as u8);
-
-                let addr = match self.addr() {
-                    Some(a) => a,
-                    _ => panic!("expected addr after JL (raw) instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.addr(&tok, "expected addr after JL (raw) instr")?;
             },
             "=" => {
                 self.machine_c.push(Opcode::CMP as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after CMP instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after CMP instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after CMP instr")?;
+                self.reg(&tok, "expected two registers after CMP instr")?;
             },
             "+" => {
                 self.machine_c.push(Opcode::ADD as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after ADD instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after ADD instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after ADD instr")?;
+                self.reg(&tok, "expected two registers after ADD instr")?;
             },
             "-" => {
                 self.machine_c.push(Opcode::SUB as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after SUB instr"),
-                } as u8;
-                
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after SUB instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after SUB instr")?;
+                self.reg(&tok, "expected two registers after SUB instr")?;
             },
             "*" => {
                 self.machine_c.push(Opcode::MUL as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after MUL instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after MUL instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after MUL instr")?;
+                self.reg(&tok, "expected two registers after MUL instr")?;
             },
             "/" => {
                 self.machine_c.push(Opcode::DIV as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after DIV instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after DIV instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after DIV instr")?;
+                self.reg(&tok, "expected two registers after DIV instr")?;
             },
             "&" => {
                 self.machine_c.push(Opcode::AND as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after AND instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after AND instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after AND instr")?;
+                self.reg(&tok, "expected two registers after AND instr")?;
             },
             "|" => {
                 self.machine_c.push(Opcode::OR as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after OR instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after OR instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after OR instr")?;
+                self.reg(&tok, "expected two registers after OR instr")?;
             },
             "^" => {
                 self.machine_c.push(Opcode::XOR as u8);
-
-                let reg_a = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after XOR instr"),
-                } as u8;
-
-                let reg_b = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected two registers after XOR instr"),
-                } as u8;
-
-                self.machine_c.push(reg_a);
-                self.machine_c.push(reg_b);
+                self.reg(&tok, "expected two registers after XOR instr")?;
+                self.reg(&tok, "expected two registers after XOR instr")?;
             },
             ">" => {
                 self.machine_c.push(Opcode::SHR as u8);
-
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected a register after SHR instr"),
-                } as u8;
-
-                self.machine_c.push(reg);
-                
-                let immed = match self.immed() {
-                    Some(i) => i,
-                    _ => panic!("expected immed after reg after SHR instr"),
-                };
-
-                self.psh_encoded_immed(immed);
+                self.reg(&tok, "expected a register after SHR instr")?;
+                self.immed(&tok, "expected immed after reg after SHR instr")?;
             },
             "<" => {
                 self.machine_c.push(Opcode::SHL as u8);
-
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected a register after SHL instr"),
-                } as u8;
-
-                self.machine_c.push(reg);
-                
-                let immed = match self.immed() {
-                    Some(i) => i,
-                    _ => panic!("expected immed after reg after SHL instr"),
-                };
-
-                self.psh_encoded_immed(immed);
+                self.reg(&tok, "expected a register after SHL instr")?;
+                self.immed(&tok, "expected immed after reg after SHL instr")?;
             },
             "str" => {
                 self.machine_c.push(Opcode::HSTORE as u8);
-
-                let addr = match self.addr() {
-                    Some(r) => r,
-                    _ => panic!("expected a addr after HSTORE instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.heap_addr(&tok, "expected a addr after HSTORE instr")?;
             },
             "strR" => {
                 self.machine_c.push(Opcode::HSTORER as u8);
-
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected a reg after HSTORES instr"),
-                } as u8;
-
-                self.machine_c.push(reg);
+                self.reg(&tok, "expected a reg after HSTORER instr")?;
             },
             "ld" => {
                 self.machine_c.push(Opcode::HLOAD as u8);
-
-                let addr = match self.addr() {
-                    Some(r) => r,
-                    _ => panic!("expected a addr after HLOAD instr"),
-                } as u8;
-
-                self.machine_c.push(addr);
+                self.heap_addr(&tok, "expected a addr after HLOAD instr")?;
             },
             "ldR" => {
                 self.machine_c.push(Opcode::HLOADR as u8);
+                self.reg(&tok, "expected a reg after HLOADR instr")?;
+            },
+            "call" => {
+                self.machine_c.push(Opcode::CALL as u8);
+                self.lbl(&tok, "expected label after CALL instr")?;
+            },
+            "callR" => {
+                self.machine_c.push(Opcode::CALLR as u8);
+                self.reg(&tok, "expected a reg after CALLR instr")?;
+            },
+            "ret" => self.machine_c.push(Opcode::RET as u8),
+            _ => return Err(self.diag(&tok, format!("invalid opcode {:?}", tok.text))),
+        };
 
-                let reg = match self.reg() {
-                    Some(r) => r,
-                    _ => panic!("expected a reg after HLOADR instr"),
-                } as u8;
+        Ok(())
+    }
 
-                self.machine_c.push(reg);
-            },
-            _ => panic!("invalid opcode {opcode:?}"),
+    /*
+        .include "path"   -> the tokens of the file at path take the place of the directive
+
+        path is looked for next to the including file first, then in each of the include dirs
+    */
+    // including holds the canonical & found paths of the file being loaded & every file that included it
+    fn load(&mut self, path: &Path, src: &str, including: &mut Vec<(PathBuf, PathBuf)>) -> Vec<Token> {
+        // anything past a null terminator is ignored
+        let src = match src.find('\0') {
+            Some(end) => &src[..end],
+            _ => src,
         };
+
+        let file = self.files.len();
+        self.files.push(SourceFile {
+            name: path.display().to_string(),
+            lines: src.lines().map(String::from).collect(),
+        });
+
+        let lexed = tokenize(src);
+
+        for e in lexed.errs {
+            let at = Token { text: String::new(), line: e.line, col: e.col, expansion: 0, file };
+            self.diags.push(self.diag(&at, e.msg));
+        }
+
+        self.comments.extend(lexed.comments.into_iter().map(|c| Comment { file, ..c }));
+
+        let toks: Vec<Token> = lexed.toks.into_iter().map(|t| Token { file, ..t }).collect();
+        let mut loaded = vec![];
+        let mut i = 0;
+
+        while i < toks.len() {
+            let tok = &toks[i];
+            i += 1;
+
+            if tok.text != ".include" {
+                loaded.push(tok.clone());
+                continue;
+            }
+
+            let args = rest_of_line(&toks, &mut i);
+            let included = match args.as_slice() {
+                [arg] => match arg.text.strip_prefix('"').and_then(|s| s.strip_suffix('"')).map(unescape) {
+                    Some(Ok(included)) => included,
+                    Some(Err(e)) => {
+                        self.diags.push(self.diag(arg, e));
+                        continue;
+                    },
+                    _ => {
+                        self.diags.push(self.diag(arg, format!("expected path string literal after .include, got {:?}", arg.text)));
+                        continue;
+                    },
+                },
+                _ => {
+                    self.diags.push(self.diag(tok, "expected a single path string literal after .include".to_string()));
+                    continue;
+                },
+            };
+
+            let at = &args[0];
+            let found = match self.find_include(path, &included) {
+                Some(found) => found,
+                _ => {
+                    self.diags.push(self.diag(at, format!("unable to find included file {included:?}")));
+                    continue;
+                },
+            };
+
+            let canonical = canonical(&found);
+            if including.iter().any(|(c, _)| *c == canonical) {
+                let chain: Vec<String> = including.iter().map(|(_, p)| p).chain([&found]).map(|p| p.display().to_string()).collect();
+                self.diags.push(self.diag(at, format!("include cycle: {}", chain.join(" -> "))));
+                continue;
+            }
+
+            let text = match fs::read_to_string(&found) {
+                Ok(text) => text,
+                Err(e) => {
+                    self.diags.push(self.diag(at, format!("unable to read included file {:?}: {e}", found.display().to_string())));
+                    continue;
+                },
+            };
+
+            including.push((canonical, found.clone()));
+            loaded.extend(self.load(&found, &text, including));
+            including.pop();
+        }
+
+        loaded
+    }
+
+    fn find_include(&self, from: &Path, included: &str) -> Option<PathBuf> {
+        let next_to = from.parent().unwrap_or(Path::new("")).join(included);
+
+        [next_to].into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.join(included)))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /*
+        .macro name param, param   -> defines a macro, the body runs until the matching .endm
+            $ u64$\param            -> \param is replaced with the invocation's argument everywhere in the body
+        .endm
+
+        name arg, arg              -> the rest of the line are the arguments (the commas are optional),
+                                      name has to be the first thing on its line
+    */
+    fn expand_macros(&mut self, toks: Vec<Token>, depth: usize) -> Vec<Token> {
+        let mut expanded = vec![];
+        let mut i = 0;
+
+        while i < toks.len() {
+            let tok = &toks[i];
+            i += 1;
+
+            if tok.text == ".macro" {
+                let header = rest_of_line(&toks, &mut i);
+
+                // .macro & .endm pairs inside of the body are nested definitions, made once the macro is expanded
+                let start = i;
+                let mut nesting = 1;
+
+                while i < toks.len() {
+                    match toks[i].text.as_str() {
+                        ".macro" => nesting += 1,
+                        ".endm" => nesting -= 1,
+                        _ => {},
+                    }
+
+                    if nesting == 0 {
+                        break;
+                    }
+
+                    i += 1;
+                }
+
+                if i >= toks.len() {
+                    self.diags.push(self.diag(tok, "expected .endm to end the macro".to_string()));
+                }
+
+                let body = toks[start..i.min(toks.len())].to_vec();
+                i += 1;
+
+                if let Err(diag) = self.define_macro(tok, &header, body) {
+                    self.diags.push(diag);
+                }
+                continue;
+            }
+
+            if tok.text == ".endm" {
+                self.diags.push(self.diag(tok, ".endm without a .macro to end".to_string()));
+                continue;
+            }
+
+            // only the first token on a line invokes a macro, so operands (e.g. a label) may share a macro's name
+            let starts_line = i == 1 || !toks[i-2].same_line(tok);
+
+            let mac = match self.macros.get(&tok.text) {
+                Some(mac) if starts_line => mac.clone(),
+                _ => {
+                    expanded.push(tok.clone());
+                    continue;
+                },
+            };
+
+            let args = rest_of_line(&toks, &mut i);
+
+            if depth >= MAX_MACRO_DEPTH {
+                self.diags.push(self.diag(tok, format!("macro {:?} is nested over {MAX_MACRO_DEPTH} deep, does it invoke itself?", tok.text)));
+                continue;
+            }
+
+            if args.len() != mac.params.len() {
+                self.diags.push(self.diag(tok, format!("macro {:?} takes {} argument(s), got {}", tok.text, mac.params.len(), args.len())));
+                continue;
+            }
+
+            self.expansions.push((tok.text.clone(), tok.clone()));
+            let expansion = self.expansions.len();
+
+            let body = mac.body.iter().map(|t| Token {
+                text: substitute(&t.text, &tok.text, expansion, &mac, &args),
+                expansion,
+                ..t.clone()
+            }).collect();
+
+            let body = self.expand_macros(body, depth+1);
+            expanded.extend(body);
+        }
+
+        expanded
     }
 
-    fn rd_til_ws(&mut self) -> String {
-        let mut buf = String::new();
+    fn define_macro(&mut self, tok: &Token, header: &[Token], body: Vec<Token>) -> Result<(), AsmDiagnostic> {
+        let (name, params) = match header.split_first() {
+            Some(parts) => parts,
+            _ => return Err(self.diag(tok, "expected macro name after .macro".to_string())),
+        };
 
-        while match self.ch { '\0'|'\n'|'\r'|'\t'|' ' => false, _ => true } {
-            buf.push(self.ch);
-            self.adv();
+        for t in header {
+            if !is_ident(&t.text) {
+                return Err(self.diag(t, format!("expected a name, got {:?}", t.text)));
+            }
         }
 
-        while match self.ch { '\n'|'\r'|' '|'\t' => true, _ => false } {
-            self.adv();
+        let mut locals = vec![];
+
+        for t in body.iter() {
+            let name = match t.text.strip_prefix('.') {
+                Some(name) if is_ident(name) && !DATA_DIRECTIVES.contains(&t.text.as_str()) && !matches!(name, "macro"|"endm"|"equ"|"define"|"entry") => name,
+                _ => continue,
+            };
+
+            // every use of a local is renamed, which would turn the register or instr into a label too
+            if is_reserved(name) {
+                return Err(self.diag(t, format!("macro label {name:?} can't share its name with a register or instr")));
+            }
+
+            locals.push(name.to_string());
         }
 
-        return buf;
+        // a later definition replaces an earlier one
+        self.macros.insert(name.text.clone(), Macro {
+            params: params.iter().map(|t| t.text.clone()).collect(),
+            locals,
+            body,
+        });
+        Ok(())
     }
 
-    fn lbl(&mut self) {
-        let name = self.rd_til_ws();
+    // the outermost macro invocation a token was produced by, or the token itself if it's straight from the source
+    fn site<'a>(&'a self, mut tok: &'a Token) -> &'a Token {
+        while tok.expansion != 0 {
+            tok = &self.expansions[tok.expansion-1].1;
+        }
 
-        self.bit += 1; // to account for the addr
-        self.lbl_replaces.push((self.bit.clone(), name));
+        tok
     }
 
-    fn psh_encoded_immed(&mut self, immed: Immediate) {
-        let mut encoded: Vec<u8> = vec![];
-    
-        match immed {
-            Immediate::None() => {
-                self.bit += 1;
-                encoded.push(255)
-            },
-            Immediate::U8(i) => {
-                self.bit += 2;
-                encoded.push(0);
-                encoded.push(i);
-            },
-            Immediate::I8(i) => {
-                self.bit += 2;
-                encoded.push(1);
-                encoded.push(i as u8);
-            },
-            Immediate::U16(i) => {
-                self.bit += 3;
-                encoded.push(2);
-                encoded.extend_from_slice(&i.to_le_bytes());
-            },
-            Immediate::I16(i) => {
-                self.bit += 3;
-                encoded.push(3);
-                encoded.extend_from_slice(&i.to_le_bytes());
-            },
-            Immediate::U32(i) => {
-                self.bit += 5;
-                encoded.push(4);
-                encoded.extend_from_slice(&i.to_le_bytes());
-            },
-            Immediate::I32(i) => {
-                self.bit += 5;
-                encoded.push(5);
-                encoded.extend_from_slice(&i.to_le_bytes());
-            },
-            Immediate::U64(i) => {
-                self.bit += 9;
-                encoded.push(6);
-                encoded.extend_from_slice(&i.to_le_bytes());
-            },
-            Immediate::I64(i) => {
-                self.bit += 9;
-                encoded.push(7);
-                encoded.extend_from_slice(&i.to_le_bytes());
-            },
-            Immediate::F32(i) => {
-                self.bit += 5;
-                encoded.push(8);
-                encoded.extend_from_slice(&i.to_le_bytes());
+    // code produced by a macro is reported at the invocation in the source, along with where in the macro(s) it went wrong
+    fn diag(&self, tok: &Token, mut msg: String) -> AsmDiagnostic {
+        let mut site = tok;
+
+        while site.expansion != 0 {
+            let (name, invocation) = &self.expansions[site.expansion-1];
+            let file = &self.files[site.file].name;
+            let at = if file.is_empty() { String::new() } else { format!("{file}:") };
+
+            msg = format!("{msg}, in macro {name:?} at {at}{}:{}", site.line, site.col);
+            site = invocation;
+        }
+
+        let file = &self.files[site.file];
+        AsmDiagnostic {
+            file: file.name.clone(),
+            line: site.line,
+            col: site.col,
+            snippet: file.lines.get(site.line-1).cloned().unwrap_or_default(),
+            msg,
+        }
+    }
+
+    // operands have to be on the same line as their instr, a missing one is reported right after the last token read
+    fn operand(&mut self, instr: &Token, msg: &str) -> Result<Token, AsmDiagnostic> {
+        match self.toks.get(self.i) {
+            Some(tok) if tok.same_line(instr) => {
+                self.i += 1;
+                Ok(tok.clone())
             },
-            Immediate::F64(i) => {
-                self.bit += 9;
-                encoded.push(9);
-                encoded.extend_from_slice(&i.to_le_bytes());
+            _ => {
+                let prev = &self.toks[self.i-1];
+
+                Err(self.diag(&Token {
+                    text: String::new(),
+                    col: prev.col + prev.text.chars().count(),
+                    ..prev.clone()
+                }, msg.to_string()))
             },
         }
+    }
+
+    /*
+        .string "Hello, world!\n"  -> null terminated U32 unicode chars
+        .u32 1, 2, 3              -> U32s (same for the other immediate types)
+    */
+    fn data_directive(&mut self, directive: &Token) -> Result<(), AsmDiagnostic> {
+        if directive.text == ".string" {
+            let tok = self.operand(directive, "expected string literal after .string")?;
+
+            let s = match tok.text.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(s) => s,
+                _ => return Err(self.diag(&tok, format!("expected string literal after .string, got {:?}", tok.text))),
+            };
+
+            let s = match unescape(s) {
+                Ok(s) => s,
+                Err(e) => return Err(self.diag(&tok, e)),
+            };
+
+            self.data.extend(s.chars().map(|c| Immediate::U32(c as u32)));
+            self.data.push(Immediate::U32(0));
+            return Ok(());
+        }
 
-        self.machine_c.extend(encoded);
+        let int_type = &directive.text[1..];
+        let msg = format!("expected {int_type} values after {}", directive.text);
+
+        loop {
+            let tok = self.expr_operand(directive, &msg)?;
+
+            // anything but a plain literal is an expression, which may have to wait for labels defined later on
+            if int_layout(int_type).is_some() && parse_int(&tok.text).is_err() {
+                match self.eval_typed(&tok, int_type) {
+                    Ok(immed) => self.data.push(immed),
+                    Err(ExprError::Unknown(_)) => {
+                        self.data_replaces.push((self.data.len(), int_type.to_string(), tok));
+                        self.data.push(Immediate::None());
+                    },
+                    Err(e) => return Err(self.diag(&tok, format!("{msg}, {e}"))),
+                }
+            } else {
+                match parse_typed(int_type, &tok.text) {
+                    Ok(immed) => self.data.push(immed),
+                    Err(e) => return Err(self.diag(&tok, format!("{msg}, {e}"))),
+                }
+            }
+
+            match self.toks.get(self.i) {
+                Some(next) if next.same_line(directive) && next.text == "," => self.i += 1,
+                _ => return Ok(()),
+            }
+        }
     }
 
-    fn reg(&mut self) -> Option<usize> {
-        let mut reg_v: Vec<char> = self.rd_til_ws().chars().collect();
-        self.bit += 1;
+    /*
+        .equ NAME value   -> every later (or earlier) use of NAME in an addr, immed or data value is replaced by value
+        .define NAME value
+    */
+    fn constant(&mut self, directive: &Token) -> Result<(), AsmDiagnostic> {
+        let name = self.operand(directive, &format!("expected name after {}", directive.text))?;
+
+        if !is_ident(&name.text) {
+            return Err(self.diag(&name, format!("expected name after {}, got {:?}", directive.text, name.text)));
+        }
 
-        if reg_v.len() < 2 {
-            return None;
+        if self.lbls.contains_key(&name.text) || self.consts.contains_key(&name.text) {
+            return Err(self.diag(&name, format!("{:?} is already defined", name.text)));
         }
 
-        if reg_v[0] != 'R' {
-            return None;
+        // the value is the rest of the line, so it may be spaced out like BUF + 4
+        let first = self.operand(&name, &format!("expected value after {} {}", directive.text, name.text))?;
+        let mut val = first.clone();
+
+        while let Some(tok) = self.toks.get(self.i).filter(|tok| tok.same_line(&first)) {
+            val.text.push_str(&tok.text);
+            self.i += 1;
         }
 
-        reg_v.remove(0);
+        // names that aren't defined yet are fine, anything else wrong with the value is reported here
+        let res = eval(&val.text, &mut |name| self.resolve(name));
+        self.consts.insert(name.text, val.clone());
+        self.forget_unresolved();
 
-        let mut reg_n_s = String::new();
+        match res {
+            Err(ExprError::Invalid(e)) => Err(self.diag(&val, e)),
+            _ => Ok(()),
+        }
+    }
 
-        for c in reg_v.into_iter() {
-            reg_n_s.push(c);
+    fn resolve(&mut self, name: &str) -> Result<i128, ExprError> {
+        if let Some(at) = self.lbls.get(name) {
+            return Ok(*at as i128);
         }
 
-        match reg_n_s.parse::<usize>() {
-            Ok(n) => Some(n),
-            Err(_) => None,
+        if let Some(res) = self.const_vals.get(name) {
+            return res.clone();
         }
+
+        let text = match self.consts.get(name) {
+            Some(val) => val.text.clone(),
+            _ => return Err(ExprError::Unknown(name.to_string())),
+        };
+
+        if self.resolving.iter().any(|c| c == name) {
+            return Err(ExprError::Invalid(format!("constant {name:?} is defined in terms of itself")));
+        }
+
+        self.resolving.push(name.to_string());
+        let res = eval(&text, &mut |name| self.resolve(name));
+        self.resolving.pop();
+
+        self.const_vals.insert(name.to_string(), res.clone());
+        res
+    }
+
+    // constants that couldn't be evaluated may be now that another label or constant is defined
+    fn forget_unresolved(&mut self) {
+        self.const_vals.retain(|_, res| res.is_ok());
     }
 
-    fn addr(&mut self) -> Option<usize> {
-        let addr_v: Vec<char> = self.rd_til_ws().chars().collect();
-        let mut addr_s = String::new();
-        self.bit += 1;
+    fn eval_typed(&mut self, tok: &Token, int_type: &str) -> Result<Immediate, ExprError> {
+        let n = eval(&tok.text, &mut |name| self.resolve(name))?;
+        int_immed(int_type, n).ok_or_else(|| ExprError::Invalid(format!("{} ({n}) is out of range for {int_type}", tok.text)))
+    }
 
-        for c in addr_v.into_iter() {
-            addr_s.push(c);
+    // writes the value of an expression, or a zeroed placeholder to be filled in once every label is known
+    fn value(&mut self, tok: Token, int_type: &str, msg: &str) -> Result<(), AsmDiagnostic> {
+        match self.eval_typed(&tok, int_type) {
+            Ok(immed) => self.machine_c.extend(&encode_immed(immed)[1..]),
+            Err(ExprError::Unknown(_)) => {
+                let size = int_layout(int_type).map_or(0, |(_, size)| size);
+                self.lbl_replaces.push((self.machine_c.len(), int_type.to_string(), tok));
+                self.machine_c.extend(vec![0; size]);
+            },
+            Err(e) => return Err(self.diag(&tok, format!("{msg}, {e}"))),
         }
 
-        match addr_s.parse::<usize>() {
-            Ok(n) => Some(n),
-            Err(_) => None,
+        Ok(())
+    }
+
+    // an operand that may be an expression, which has to be a single token as the next one could start another instr
+    fn expr_operand(&mut self, instr: &Token, msg: &str) -> Result<Token, AsmDiagnostic> {
+        let tok = self.operand(instr, msg)?;
+        let next = self.toks.get(self.i).filter(|next| next.same_line(&tok));
+        let after = self.toks.get(self.i+1).filter(|after| next.is_some_and(|next| after.same_line(next)));
+
+        let is_reg = |t: &Token| t.text.strip_prefix('R').is_some_and(|n| n.parse::<u8>().is_ok());
+        let is_op = |c: char| matches!(c, '+'|'-'|'*'|'/');
+
+        // B + 4 would otherwise be B followed by an ADD, & B +4 by an unknown instr
+        let spaced = match next.map(|next| next.text.as_str()) {
+            Some("+"|"-"|"*"|"/") => !after.is_some_and(is_reg),
+            Some(text) if text.starts_with(is_op) => text.chars().nth(1).is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '('),
+            Some(_) => tok.text.ends_with(|c| is_op(c) || c == '('),
+            _ => false,
+        };
+
+        if spaced {
+            let joined: String = [Some(&tok), next, after].into_iter().flatten().map(|t| t.text.as_str()).collect();
+            return Err(self.diag(&tok, format!("expressions can't contain spaces, did you mean {joined}?")));
         }
+
+        Ok(tok)
     }
 
-    fn immed(&mut self) -> Option<Immediate> {
-        let mut int: Vec<char> = self.rd_til_ws().chars().collect();
+    fn lbl(&mut self, instr: &Token, msg: &str) -> Result<(), AsmDiagnostic> {
+        let tok = self.expr_operand(instr, msg)?;
+        self.lbl_uses.push((tok.clone(), false));
+        self.value(tok, "u32", msg)
+    }
 
-        if int.len() < 4 {
-            return None;
+    fn reg(&mut self, instr: &Token, msg: &str) -> Result<(), AsmDiagnostic> {
+        let tok = self.operand(instr, msg)?;
+
+        let reg = match tok.text.strip_prefix('R').map(|n| n.parse::<u8>()) {
+            Some(Ok(n)) => n,
+            _ => return Err(self.diag(&tok, format!("{msg}, got {:?}", tok.text))),
+        };
+
+        if reg as Register >= REGISTER_COUNT {
+            return Err(self.diag(&tok, format!("register R{reg} doesn't exist, only R0 to R{} do", REGISTER_COUNT-1)));
         }
 
-        let is_neg = {
-            if int[0] == '-' {
-                int.remove(0);
-                true
-            } else {
-                false
-            }
+        self.machine_c.push(reg);
+        Ok(())
+    }
+
+    fn addr(&mut self, instr: &Token, msg: &str) -> Result<(), AsmDiagnostic> {
+        let tok = self.expr_operand(instr, msg)?;
+
+        let addr = match parse_int(&tok.text) {
+            Ok(n) => match u32::try_from(n) {
+                Ok(addr) => addr,
+                _ => return Err(self.diag(&tok, format!("address {} is out of range for u32", tok.text))),
+            },
+            _ => return self.value(tok, "u32", msg),
         };
 
-        let t: char = int[0].clone();
-        int.remove(0);
+        self.machine_c.extend_from_slice(&addr.to_le_bytes());
+        Ok(())
+    }
+
+    fn heap_addr(&mut self, instr: &Token, msg: &str) -> Result<(), AsmDiagnostic> {
+        self.addr(instr, msg)?;
+        self.lbl_uses.push((self.toks[self.i-1].clone(), true));
+        Ok(())
+    }
+
+    fn immed(&mut self, instr: &Token, msg: &str) -> Result<(), AsmDiagnostic> {
+        let tok = self.expr_operand(instr, msg)?;
+
+        // integer immediates can also be expressions using labels & constants, e.g. u64$msg or u32$BUF+4
+        if let Some((int_type, val)) = tok.text.split_once('$') {
+            if let (Some((tag, _)), Err(_)) = (int_layout(int_type), parse_int(val)) {
+                self.machine_c.push(tag);
+
+                return self.value(Token {
+                    text: val.to_string(),
+                    col: tok.col + int_type.len() + 1,
+                    ..tok.clone()
+                }, int_type, msg);
+            }
+        }
+
+        match parse_immed(&tok.text) {
+            Ok(immed) => {
+                self.machine_c.extend(encode_immed(immed));
+                Ok(())
+            },
+            Err(e) => Err(self.diag(&tok, format!("{msg}, {e}"))),
+        }
+    }
+}
+
+fn encode_immed(immed: Immediate) -> Vec<u8> {
+    let mut encoded: Vec<u8> = vec![];
+
+    match immed {
+        Immediate::None() => {
+            encoded.push(255)
+        },
+        Immediate::U8(i) => {
+            encoded.push(0);
+            encoded.push(i);
+        },
+        Immediate::I8(i) => {
+            encoded.push(1);
+            encoded.push(i as u8);
+        },
+        Immediate::U16(i) => {
+            encoded.push(2);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::I16(i) => {
+            encoded.push(3);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::U32(i) => {
+            encoded.push(4);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::I32(i) => {
+            encoded.push(5);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::U64(i) => {
+            encoded.push(6);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::I64(i) => {
+            encoded.push(7);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::F32(i) => {
+            encoded.push(8);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+        Immediate::F64(i) => {
+            encoded.push(9);
+            encoded.extend_from_slice(&i.to_le_bytes());
+        },
+    }
+
+    encoded
+}
+
+// typed immediates look like u32$65 or -i8$5
+fn parse_immed(s: &str) -> Result<Immediate, String> {
+    let (is_neg, rest) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        _ => (false, s),
+    };
 
-        match t {
-            'i' => {},
-            'u' => {},
-            'f' => {},
-            _ => return None,
+    let (int_type, val) = match rest.split_once('$') {
+        Some(parts) => parts,
+        _ => return Err(format!("got {s:?}")),
+    };
+
+    if is_neg && int_type.starts_with('u') {
+        return Err(format!("unsigned immediate {s:?} can't be negative"));
+    }
+
+    // parse with the sign so that e.g. -i8$128 fits
+    parse_typed(int_type, &if is_neg { format!("-{val}") } else { val.to_string() })
+}
+
+fn parse_typed(int_type: &str, val: &str) -> Result<Immediate, String> {
+    if let "f32"|"f64" = int_type {
+        let float = val.replace('_', "");
+        let immed = match int_type {
+            "f32" => float.parse().ok().map(Immediate::F32),
+            _ => float.parse().ok().map(Immediate::F64),
         };
 
-        if t == 'u' && is_neg {
-            return None;
-        }
-
-        let mut int_type = t.to_string();
-
-        while int[0] != '$' && int.len() > 0 {
-            int_type.push(int[0].clone());
-            int.remove(0);
-        }
-
-        if int[0] != '$' {
-            return None;
-        }
-
-        int.remove(0);
-
-        let mut int_str = String::new();
-
-        while int.len() > 0 {
-            int_str.push(int[0].clone());
-            int.remove(0);
-        }
-
-        return Some(match int_type.as_str() {
-            "u8" => Immediate::U8(int_str.parse::<u8>().unwrap()),
-            "u16" => Immediate::U16(int_str.parse::<u16>().unwrap()),
-            "u32" => Immediate::U32(int_str.parse::<u32>().unwrap()),
-            "u64" => Immediate::U64(int_str.parse::<u64>().unwrap()),
-            "i8" => Immediate::I8({
-                let i = int_str.parse::<i8>().unwrap();
-                if is_neg { -i } else { i }
-            }),
-            "i16" => Immediate::I16({
-                let i = int_str.parse::<i16>().unwrap();
-                if is_neg { -i } else { i }
-            }),
-            "i32" => Immediate::I32({
-                let i = int_str.parse::<i32>().unwrap();
-                if is_neg { -i } else { i }
-            }),
-            "i64" => Immediate::I64({
-                let i = int_str.parse::<i64>().unwrap();
-                if is_neg { -i } else { i }
-            }),
-            "f32" => Immediate::F32({
-                let f = int_str.parse::<f32>().unwrap();
-                if is_neg { -f } else { f }
-            }),
-            "f64" => Immediate::F64({
-                let f = int_str.parse::<f64>().unwrap();
-                if is_neg { -f } else { f }
-            }),
-            _ => return None,
-        });
+        return immed.ok_or_else(|| format!("{val:?} isn't a valid {int_type}"));
+    }
+
+    if int_layout(int_type).is_none() {
+        return Err(format!("unknown immediate type {int_type:?}"));
     }
 
-    fn adv(&mut self) {
-        if self.ch == '\0' {
-            return;
+    int_immed(int_type, parse_int(val)?).ok_or_else(|| format!("{val} is out of range for {int_type}"))
+}
+
+// None if n doesn't fit in int_type
+fn int_immed(int_type: &str, n: i128) -> Option<Immediate> {
+    match int_type {
+        "u8" => u8::try_from(n).ok().map(Immediate::U8),
+        "u16" => u16::try_from(n).ok().map(Immediate::U16),
+        "u32" => u32::try_from(n).ok().map(Immediate::U32),
+        "u64" => u64::try_from(n).ok().map(Immediate::U64),
+        "i8" => i8::try_from(n).ok().map(Immediate::I8),
+        "i16" => i16::try_from(n).ok().map(Immediate::I16),
+        "i32" => i32::try_from(n).ok().map(Immediate::I32),
+        "i64" => i64::try_from(n).ok().map(Immediate::I64),
+        _ => None,
+    }
+}
+
+// encoded tag & value size of the integer immediate types
+fn int_layout(int_type: &str) -> Option<(u8, usize)> {
+    match int_type {
+        "u8" => Some((0, 1)),
+        "i8" => Some((1, 1)),
+        "u16" => Some((2, 2)),
+        "i16" => Some((3, 2)),
+        "u32" => Some((4, 4)),
+        "i32" => Some((5, 4)),
+        "u64" => Some((6, 8)),
+        "i64" => Some((7, 8)),
+        _ => None,
+    }
+}
+
+// the tokens after i on the same line as the one before it, commas left out
+fn rest_of_line(toks: &[Token], i: &mut usize) -> Vec<Token> {
+    let start = *i;
+
+    while *i < toks.len() && toks[*i].same_line(&toks[start-1]) {
+        *i += 1;
+    }
+
+    toks[start..*i].iter().filter(|t| t.text != ",").cloned().collect()
+}
+
+// a body token with its local labels renamed & \params replaced by the arguments
+fn substitute(text: &str, name: &str, expansion: usize, mac: &Macro, args: &[Token]) -> String {
+    let text = map_idents(text, |ident| {
+        mac.locals.iter().any(|l| l == ident).then(|| format!("__{name}_{expansion}_{ident}"))
+    });
+
+    let mut buf = String::new();
+    let mut rest = &text[..];
+
+    while let Some(at) = rest.find('\\') {
+        buf.push_str(&rest[..at]);
+        rest = &rest[at+1..];
+
+        let param_len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        match mac.params.iter().position(|p| *p == rest[..param_len]) {
+            Some(param) => {
+                buf.push_str(&args[param].text);
+                rest = &rest[param_len..];
+            },
+            _ => buf.push('\\'),
         }
+    }
 
-        self.i += 1;
-        self.ch = self.src[self.i];
+    buf.push_str(rest);
+    buf
+}
+
+// replaces the identifiers in text that aren't inside of quotes, part of a number or a \param, e.g. loop in u32$loop+4
+fn map_idents(text: &str, mut f: impl FnMut(&str) -> Option<String>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut buf = String::new();
+    let mut quote = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+        match quote {
+            Some(q) => {
+                if ch == '\\' && i+1 < chars.len() {
+                    buf.push(ch);
+                    i += 1;
+                } else if ch == q {
+                    quote = None;
+                }
+
+                buf.push(chars[i]);
+                i += 1;
+            },
+            _ if ch == '"' || ch == '\'' => {
+                quote = Some(ch);
+                buf.push(ch);
+                i += 1;
+            },
+            _ if (ch.is_alphabetic() || ch == '_') && !(i > 0 && (is_word(chars[i-1]) || chars[i-1] == '\\')) => {
+                let start = i;
+                while i < chars.len() && is_word(chars[i]) {
+                    i += 1;
+                }
+
+                let ident: String = chars[start..i].iter().collect();
+                buf.push_str(&f(&ident).unwrap_or(ident));
+            },
+            _ => {
+                buf.push(ch);
+                i += 1;
+            },
+        }
     }
-}
\ No newline at end of file
+
+    buf
+}
+
+// the same file can be reached through different paths, e.g. lib/../a & a
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// registers & the instrs that are identifiers
+fn is_reserved(name: &str) -> bool {
+    let is_reg = name.strip_prefix('R').is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    is_reg || matches!(name, "_"|"hlt"|"int"|"str"|"strR"|"ld"|"ldR"|"call"|"callR"|"ret")
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
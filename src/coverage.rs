@@ -0,0 +1,162 @@
+use {
+    crate::{
+        disassembler::decode_all,
+        program::Program,
+        trace::TraceEvent,
+        vm::{Address, Instruction},
+    },
+    std::{collections::BTreeMap, fmt::Write},
+};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// which instrs & which ways of every conditional jump a program executed, record is meant to be the VM's tracer
+pub struct Coverage {
+    hits: Vec<u64>,                      // times the instr starting at every bit position was executed
+    branches: BTreeMap<Address, Branch>, // by the address of the JE, JNE, JG or JL
+}
+
+impl Coverage {
+    pub fn new(program: &Program) -> Self {
+        Self { hits: vec![0; program.code.len()], branches: BTreeMap::new() }
+    }
+
+    pub fn record(&mut self, event: &TraceEvent) {
+        self.hits[event.instr_ptr] += 1;
+
+        match event.instr {
+            // a jump to the instr right after it counts as taken, as there's no telling the two apart
+            Instruction::JE(at) | Instruction::JNE(at) | Instruction::JG(at) | Instruction::JL(at) => {
+                let branch = self.branches.entry(event.instr_ptr).or_default();
+
+                match event.next == at {
+                    true => branch.taken += 1,
+                    _ => branch.not_taken += 1,
+                }
+            },
+            _ => {},
+        }
+    }
+
+    pub fn hits(&self, addr: Address) -> u64 {
+        self.hits.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn branch(&self, addr: Address) -> Branch {
+        self.branches.get(&addr).copied().unwrap_or_default()
+    }
+
+    // e.g. "instructions: 10/12 (83.3%), branches: 3/4 (75.0%)", counting every instr & way of a branch the program has,
+    // or only those from the source if the program has a debug table (leaving out the HLT the assembler appends)
+    pub fn summary(&self, program: &Program) -> String {
+        let instrs: Vec<(Address, Instruction)> = decode_all(&program.code).into_iter()
+            .filter(|l| program.debug.lines.is_empty() || program.debug.line_at(l.addr).is_some())
+            .filter_map(|l| l.instr.map(|instr| (l.addr, instr)))
+            .collect();
+
+        let hit = instrs.iter().filter(|(addr, _)| self.hits(*addr) > 0).count();
+        let jumps: Vec<Branch> = instrs.iter().filter(|(_, instr)| is_branch(*instr)).map(|(addr, _)| self.branch(*addr)).collect();
+        let ways_hit: usize = jumps.iter().map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize).sum();
+
+        format!(
+            "instructions: {hit}/{} ({}), branches: {ways_hit}/{} ({})",
+            instrs.len(), percent(hit, instrs.len()), jumps.len()*2, percent(ways_hit, jumps.len()*2),
+        )
+    }
+
+    /*
+        an lcov tracefile with a record per source file in the program's debug table, e.g.
+
+        TN:
+        SF:tests/loop.artofasm
+        DA:3,1
+        DA:5,4
+        BRDA:5,0,0,3
+        BRDA:5,0,1,1
+        BRF:2
+        BRH:2
+        LF:2
+        LH:2
+        end_of_record
+
+        a line's count is that of its most executed instr, & a line with several branches (e.g. from a macro)
+        gets a block per branch, in the order they're in the code
+
+        instrs without a debug line entry (like the HLT the assembler appends) aren't in the report,
+        so a program without a debug table gives an empty one, & neither are sources without a path
+        (e.g. from Assembler::new), as lcov tools can't tell which file they are
+    */
+    pub fn lcov(&self, program: &Program) -> String {
+        // file -> line -> (count, the branches on it)
+        let mut files: BTreeMap<usize, BTreeMap<usize, (u64, Vec<Address>)>> = BTreeMap::new();
+
+        for line in decode_all(&program.code) {
+            let (Some(instr), Some(entry)) = (line.instr, program.debug.line_at(line.addr)) else {
+                continue;
+            };
+
+            let (count, branches) = files.entry(entry.file).or_default().entry(entry.line).or_default();
+            *count = (*count).max(self.hits(line.addr));
+
+            if is_branch(instr) {
+                branches.push(line.addr);
+            }
+        }
+
+        let mut out = String::new();
+
+        for (file, lines) in files {
+            let name = match program.debug.files.get(file) {
+                Some(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+
+            out.push_str("TN:\n");
+            writeln!(out, "SF:{name}").unwrap();
+
+            for (line, (count, _)) in lines.iter() {
+                writeln!(out, "DA:{line},{count}").unwrap();
+            }
+
+            let (mut found, mut hit) = (0, 0);
+
+            for (line, (count, branches)) in lines.iter() {
+                for (block, addr) in branches.iter().enumerate() {
+                    let branch = self.branch(*addr);
+
+                    for (i, taken) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                        // - marks a branch whose line never ran, rather than one that ran but never went that way
+                        match *count {
+                            0 => writeln!(out, "BRDA:{line},{block},{i},-").unwrap(),
+                            _ => writeln!(out, "BRDA:{line},{block},{i},{taken}").unwrap(),
+                        }
+
+                        found += 1;
+                        hit += (taken > 0) as usize;
+                    }
+                }
+            }
+
+            writeln!(out, "BRF:{found}\nBRH:{hit}").unwrap();
+            writeln!(out, "LF:{}\nLH:{}", lines.len(), lines.values().filter(|(count, _)| *count > 0).count()).unwrap();
+            out.push_str("end_of_record\n");
+        }
+
+        out
+    }
+}
+
+fn is_branch(instr: Instruction) -> bool {
+    matches!(instr, Instruction::JE(_) | Instruction::JNE(_) | Instruction::JG(_) | Instruction::JL(_))
+}
+
+fn percent(n: usize, of: usize) -> String {
+    match of {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", n as f64 / of as f64 * 100.0),
+    }
+}
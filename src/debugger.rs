@@ -0,0 +1,438 @@
+use {
+    crate::{
+        disassembler::{decode_all, format_immed, format_instr, labels, Line},
+        program::Program,
+        vm::{decode, Address, ExitStatus, Instruction, Location, Register, VirtualMachine, VmError, Watch, REGISTER_COUNT},
+    },
+    std::{collections::BTreeMap, fmt::Write, fs::read_to_string},
+};
+
+// how many instrs are shown before & after the next one to execute
+const CONTEXT_INSTRS: usize = 2;
+
+pub const HELP: &str = "\
+commands:
+    break (addr|label), b    pause before the instr at addr
+    delete (addr|label), d   remove a breakpoint
+    breaks                   list breakpoints
+    step [n], s              execute n (1 by default) instrs, stopping early at a breakpoint
+    next, n                  execute an instr, running CALLs until they return
+    continue, c              run until a breakpoint is hit or the program halts
+    list, l                  show where execution is
+    reg [n]                  print every register, or just Rn
+    flags                    print flag_eq & flag_gt
+    stack                    print the stack, bottom first
+    heap (start) [end]       print heap cells start up to (not including) end
+    watch (Rn|start) [end], w
+                             pause after an instr writes to Rn or heap cells start up to (not including) end,
+                             start can also be a data label
+    unwatch (Rn|start) [end] remove a watchpoint
+    watches                  list watchpoints
+    restart                  start the program over, keeping breakpoints & watchpoints
+    help, h                  print this
+    quit, q";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Break(String),
+    Delete(String),
+    Breaks,
+    Step(usize),
+    Next,
+    Continue,
+    List,
+    Reg(Option<Register>),
+    Flags,
+    Stack,
+    Heap(Address, Option<Address>),
+    Watch(String, Option<Address>),      // a register, or a heap address or data label & the end of the range
+    Unwatch(String, Option<Address>),
+    Watches,
+    Restart,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+
+        let arg = |i: usize| args.get(i).copied().ok_or(format!("{cmd} expects {} argument(s)", i+1));
+
+        let cmd = match cmd {
+            "break" | "b" => Command::Break(arg(0)?.to_string()),
+            "delete" | "d" => Command::Delete(arg(0)?.to_string()),
+            "breaks" => Command::Breaks,
+            "step" | "s" => Command::Step(args.first().map(|n| number(n)).transpose()?.unwrap_or(1)),
+            "next" | "n" => Command::Next,
+            "continue" | "c" => Command::Continue,
+            "list" | "l" => Command::List,
+            "reg" => match args.first() {
+                Some(reg) => Command::Reg(Some(number(reg.trim_start_matches(['R', 'r']))?)),
+                _ => Command::Reg(None),
+            },
+            "flags" => Command::Flags,
+            "stack" => Command::Stack,
+            "heap" => Command::Heap(number(arg(0)?)?, args.get(1).map(|end| number(end)).transpose()?),
+            "watch" | "w" => Command::Watch(arg(0)?.to_string(), args.get(1).map(|end| number(end)).transpose()?),
+            "unwatch" => Command::Unwatch(arg(0)?.to_string(), args.get(1).map(|end| number(end)).transpose()?),
+            "watches" => Command::Watches,
+            "restart" => Command::Restart,
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => return Err(format!("unknown command {cmd:?}, try help")),
+        };
+
+        Ok(cmd)
+    }
+}
+
+fn describe(watch: &Watch) -> String {
+    match watch {
+        Watch::Heap(range) if range.len() == 1 => format!("heap[{}]", range.start),
+        Watch::Heap(range) => format!("heap[{}..{}]", range.start, range.end),
+        Watch::Register(reg) => format!("R{reg}"),
+    }
+}
+
+// decimal or 0x prefixed hex
+fn number(s: &str) -> Result<usize, String> {
+    let res = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        _ => s.parse(),
+    };
+
+    res.map_err(|_| format!("expected a number, got {s:?}"))
+}
+
+// steps through a program, pausing at breakpoints
+pub struct Debugger {
+    program: Program,
+    heap_max: usize,
+    vm: VirtualMachine,
+    breakpoints: Vec<Address>,
+    lines: Vec<Line>,                    // the disassembled code
+    lbls: BTreeMap<Address, String>,
+    sources: Vec<Option<Vec<String>>>,   // lines of every file in the debug table, None if it can't be read
+}
+
+impl Debugger {
+    pub fn new(program: Program, heap_max: usize) -> Self {
+        let lines = decode_all(&program.code);
+        let lbls = labels(&lines, &program.symbols);
+        let sources = program.debug.files.iter()
+            .map(|path| read_to_string(path).ok().map(|src| src.lines().map(str::to_string).collect()))
+            .collect();
+
+        Self {
+            vm: VirtualMachine::from_program(program.clone(), heap_max),
+            program,
+            heap_max,
+            breakpoints: vec![],
+            lines,
+            lbls,
+            sources,
+        }
+    }
+
+    // the VM being debugged, e.g. to redirect its IO or register interrupts
+    pub fn vm(&mut self) -> &mut VirtualMachine {
+        &mut self.vm
+    }
+
+    pub fn breakpoints(&self) -> &[Address] {
+        &self.breakpoints
+    }
+
+    // runs cmd, returning what to print
+    pub fn run(&mut self, cmd: Command) -> String {
+        match cmd {
+            Command::Break(at) => match self.resolve(&at) {
+                Ok(addr) if self.breakpoints.contains(&addr) => format!("there's already a breakpoint at {}", self.name(addr)),
+                Ok(addr) => {
+                    self.breakpoints.push(addr);
+                    format!("breakpoint {} at {}", self.breakpoints.len(), self.name(addr))
+                },
+                Err(e) => e,
+            },
+            Command::Delete(at) => match self.resolve(&at) {
+                Ok(addr) => match self.breakpoints.iter().position(|bp| *bp == addr) {
+                    Some(i) => {
+                        self.breakpoints.remove(i);
+                        format!("deleted the breakpoint at {}", self.name(addr))
+                    },
+                    _ => format!("there's no breakpoint at {}", self.name(addr)),
+                },
+                Err(e) => e,
+            },
+            Command::Breaks => match self.breakpoints.is_empty() {
+                true => "no breakpoints".to_string(),
+                _ => self.breakpoints.iter().enumerate()
+                    .map(|(i, addr)| format!("{}: {}", i+1, self.name(*addr)))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            },
+            Command::Step(n) => {
+                // stops early at a breakpoint, other than the one execution may be paused at
+                let bps = self.breakpoints.clone();
+                let mut executed = 0;
+
+                let res = self.vm.run_until(|vm| {
+                    let stop = executed == n || (executed > 0 && bps.contains(&vm.instr_ptr()));
+                    executed += 1;
+                    stop
+                });
+
+                self.stopped(res)
+            },
+            Command::Next => {
+                let depth = self.vm.call_stack().len();
+
+                match self.vm.next_instr() {
+                    Some(Instruction::CALL(_) | Instruction::CALLR(_)) => {
+                        let bps = self.breakpoints.clone();
+                        let res = match self.vm.run_for(1) {
+                            Ok(ExitStatus::Paused) => self.vm.run_until(|vm| vm.call_stack().len() <= depth || bps.contains(&vm.instr_ptr())),
+                            res => res,
+                        };
+
+                        self.stopped(res)
+                    },
+                    _ => self.run(Command::Step(1)),
+                }
+            },
+            Command::Continue => {
+                // step off of the breakpoint that execution is paused at first
+                let bps = self.breakpoints.clone();
+                let res = match self.vm.run_for(1) {
+                    Ok(ExitStatus::Paused) => self.vm.run_until(|vm| bps.contains(&vm.instr_ptr())),
+                    res => res,
+                };
+
+                self.stopped(res)
+            },
+            Command::Watch(at, end) => match self.watch_of(&at, end) {
+                Ok(watch) if self.vm.watches().contains(&watch) => format!("{} is already watched", describe(&watch)),
+                Ok(watch) => {
+                    let msg = format!("watching {}", describe(&watch));
+                    self.vm.add_watch(watch);
+                    msg
+                },
+                Err(e) => e,
+            },
+            Command::Unwatch(at, end) => match self.watch_of(&at, end) {
+                Ok(watch) => match self.vm.remove_watch(&watch) {
+                    true => format!("stopped watching {}", describe(&watch)),
+                    _ => format!("{} isn't watched", describe(&watch)),
+                },
+                Err(e) => e,
+            },
+            Command::Watches => match self.vm.watches().is_empty() {
+                true => "no watchpoints".to_string(),
+                _ => self.vm.watches().iter().map(describe).collect::<Vec<String>>().join("\n"),
+            },
+            Command::List => self.context(),
+            Command::Reg(Some(reg)) => match self.vm.registers().get(reg) {
+                Some(immed) => format!("R{reg} = {}", format_immed(*immed)),
+                _ => format!("register R{reg} doesn't exist, only R0 to R{} do", REGISTER_COUNT-1),
+            },
+            Command::Reg(None) => self.vm.registers().iter().enumerate()
+                .map(|(i, immed)| format!("R{i:<2} = {}", format_immed(*immed)))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Command::Flags => format!("flag_eq = {}\nflag_gt = {}", self.vm.flag_eq(), self.vm.flag_gt()),
+            Command::Stack => match self.vm.stack().is_empty() {
+                true => "the stack is empty".to_string(),
+                _ => self.vm.stack().iter().enumerate()
+                    .map(|(i, immed)| format!("[{i}] {}", format_immed(*immed)))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            },
+            Command::Heap(start, end) => {
+                let heap = self.vm.heap();
+                let end = end.or(start.checked_add(1)).unwrap_or(usize::MAX).min(heap.len());
+
+                match start < end {
+                    true => (start..end)
+                        .map(|addr| format!("[{addr}] {}", format_immed(heap[addr])))
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                    _ => format!("the heap only has {} cells", heap.len()),
+                }
+            },
+            Command::Restart => {
+                let watches = self.vm.watches().to_vec();
+                self.vm = VirtualMachine::from_program(self.program.clone(), self.heap_max);
+
+                for watch in watches {
+                    self.vm.add_watch(watch);
+                }
+
+                self.context()
+            },
+            Command::Help => HELP.to_string(),
+            Command::Quit => String::new(),
+        }
+    }
+
+    // parses & runs a line typed at the prompt
+    pub fn command(&mut self, line: &str) -> String {
+        match Command::parse(line) {
+            Ok(cmd) => self.run(cmd),
+            Err(e) => e,
+        }
+    }
+
+    // a code label or an address
+    fn resolve(&self, at: &str) -> Result<Address, String> {
+        if let Ok(addr) = number(at) {
+            return match addr <= self.program.code.len() {
+                true => Ok(addr),
+                _ => Err(format!("{addr} is past the end of the program")),
+            };
+        }
+
+        let at = at.strip_prefix('.').unwrap_or(at);
+        self.program.symbols.iter()
+            .find(|sym| !sym.is_data && sym.name == at)
+            .map(|sym| sym.addr)
+            .ok_or(format!("no label named {at:?}"))
+    }
+
+    // the address, with its label if it has one
+    fn name(&self, addr: Address) -> String {
+        match self.lbls.get(&addr) {
+            Some(lbl) => format!("{addr} (.{lbl})"),
+            _ => addr.to_string(),
+        }
+    }
+
+    // a register (R3), or a heap address or label & the end of the range
+    fn watch_of(&self, at: &str, end: Option<Address>) -> Result<Watch, String> {
+        if let Some(reg) = at.strip_prefix(['R', 'r']).and_then(|reg| number(reg).ok()) {
+            return match (reg < self.vm.registers().len(), end) {
+                (true, None) => Ok(Watch::Register(reg)),
+                (true, _) => Err("registers can't be watched as a range".to_string()),
+                _ => Err(format!("register R{reg} doesn't exist, only R0 to R{} do", REGISTER_COUNT-1)),
+            };
+        }
+
+        let start = match number(at) {
+            Ok(addr) => addr,
+            _ => {
+                let at = at.strip_prefix('.').unwrap_or(at);
+                self.program.symbols.iter()
+                    .find(|sym| sym.is_data && sym.name == at)
+                    .map(|sym| sym.addr)
+                    .ok_or(format!("no data label named {at:?}"))?
+            },
+        };
+
+        // None if start is the last address there is, which no heap is big enough for
+        match end.or(start.checked_add(1)) {
+            Some(end) if end <= start => Err(format!("the range {start}..{end} is empty")),
+            Some(end) if end <= self.vm.heap().len() => Ok(Watch::Heap(start..end)),
+            _ => Err(format!("the heap only has {} cells", self.vm.heap().len())),
+        }
+    }
+
+    fn stopped(&mut self, res: Result<ExitStatus, VmError>) -> String {
+        let mut out = String::new();
+
+        for hit in self.vm.take_watch_hits() {
+            let at = match self.program.debug.location(hit.instr_ptr) {
+                Some(loc) => format!("{} ({loc})", hit.instr_ptr),
+                _ => hit.instr_ptr.to_string(),
+            };
+
+            let location = match hit.location {
+                Location::Heap(addr) => format!("heap[{addr}]"),
+                Location::Register(reg) => format!("R{reg}"),
+            };
+
+            writeln!(out, "{location} written at {at}: {} -> {}", format_immed(hit.old), format_immed(hit.new)).unwrap();
+        }
+
+        match res {
+            Ok(ExitStatus::Halted) => out.push_str("the program has halted"),
+            Ok(ExitStatus::Paused) => match self.breakpoints.iter().position(|bp| *bp == self.vm.instr_ptr()) {
+                Some(i) => write!(out, "hit breakpoint {}\n{}", i+1, self.context()).unwrap(),
+                _ => out.push_str(&self.context()),
+            },
+            Ok(_) => out.push_str(&self.context()),
+            Err(e) => out.push_str(&self.fault(e)),
+        }
+
+        out
+    }
+
+    fn fault(&self, e: VmError) -> String {
+        match self.program.debug.location(e.instr_ptr) {
+            Some(loc) => format!("{loc}: {e}"),
+            _ => e.to_string(),
+        }
+    }
+
+    /*
+        where execution is paused, e.g.
+
+        tests/hello_world.artofasm:8: int 0
+           0  $ u64$0
+        => 10  int 0
+           15  hlt
+
+        the source line is only shown if the program has a debug table & the file can be read
+    */
+    fn context(&self) -> String {
+        if self.vm.is_halted() {
+            return "the program has halted".to_string();
+        }
+
+        let ip = self.vm.instr_ptr();
+        let mut out = String::new();
+
+        if let Some(entry) = self.program.debug.line_at(ip) {
+            let loc = self.program.debug.location(ip).unwrap_or_default();
+            let text = self.sources.get(entry.file)
+                .and_then(Option::as_ref)
+                .and_then(|lines| lines.get(entry.line-1));
+
+            match text {
+                Some(text) => writeln!(out, "{loc}: {}", text.trim()).unwrap(),
+                _ => writeln!(out, "{loc}").unwrap(),
+            }
+        }
+
+        let shown: Vec<Line> = match self.lines.iter().position(|l| l.addr == ip) {
+            Some(i) => self.lines[i.saturating_sub(CONTEXT_INSTRS)..(i+CONTEXT_INSTRS+1).min(self.lines.len())].to_vec(),
+            // a jump into the middle of an instr, which the disassembly doesn't know about
+            _ => decode(&self.program.code, ip).into_iter()
+                .map(|(instr, len)| Line { addr: ip, bytes: self.program.code[ip..ip+len].to_vec(), instr: Some(instr) })
+                .collect(),
+        };
+
+        let width = shown.iter().map(|l| l.addr.to_string().len()).max().unwrap_or(0);
+
+        for line in shown.iter() {
+            if let Some(lbl) = self.lbls.get(&line.addr) {
+                writeln!(out, "   .{lbl}").unwrap();
+            }
+
+            let marker = match line.addr == ip {
+                true => "=>",
+                _ => "  ",
+            };
+
+            let asm = match line.instr {
+                Some(instr) => format_instr(instr, &self.lbls),
+                _ => "; undecodable".to_string(),
+            };
+
+            writeln!(out, "{marker} {:>width$}  {asm}", line.addr).unwrap();
+        }
+
+        out.trim_end().to_string()
+    }
+}
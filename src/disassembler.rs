@@ -0,0 +1,267 @@
+use {
+    crate::{
+        assembler::Opcode, program::{Program, Symbol}, vm::{decode, Address, Immediate, Instruction},
+    },
+    std::{collections::BTreeMap, fmt::Write},
+};
+
+// how many data values go on a single line of a data directive
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub addr: Address,
+    pub bytes: Vec<u8>,
+    pub instr: Option<Instruction>,      // None if the bytes don't decode, i.e. an unknown opcode or a cut off instruction
+}
+
+// decodes every instruction in code, one after another
+pub fn decode_all(code: &[u8]) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut addr = 0;
+
+    while addr < code.len() {
+        let (instr, len) = match decode(code, addr) {
+            Some((instr, len)) if code[addr] <= Opcode::RET as u8 => (Some(instr), len),
+            Some(_) => (None, 1),
+            _ => (None, code.len()-addr),
+        };
+
+        lines.push(Line { addr, bytes: code[addr..addr+len].to_vec(), instr });
+        addr += len;
+    }
+
+    lines
+}
+
+// a label for every code symbol & every jump & call target that an instruction starts at,
+// targets without a symbol get a synthesized lbl_[addr]
+pub fn labels(lines: &[Line], symbols: &[Symbol]) -> BTreeMap<Address, String> {
+    let mut lbls: BTreeMap<Address, String> = lines.iter()
+        .filter_map(|l| l.instr.and_then(Instruction::target))
+        .filter(|at| lines.iter().any(|l| l.addr == *at))
+        .map(|at| (at, format!("lbl_{at}")))
+        .collect();
+
+    // the first symbol defined at an address names it
+    for sym in symbols.iter().rev().filter(|sym| !sym.is_data && lines.iter().any(|l| l.addr == sym.addr)) {
+        lbls.insert(sym.addr, sym.name.clone());
+    }
+
+    lbls
+}
+
+// the instruction in ArtOfASM, jump targets are named by lbls where they can be
+pub fn format_instr(instr: Instruction, lbls: &BTreeMap<Address, String>) -> String {
+    let target = |at: Address| lbls.get(&at).cloned().unwrap_or_else(|| at.to_string());
+
+    match instr {
+        Instruction::NOP() => "_".to_string(),
+        Instruction::HLT() => "hlt".to_string(),
+        Instruction::INT(i) => format!("int {i}"),
+        Instruction::PUSH(immed) => format!("$ {}", format_immed(immed)),
+        Instruction::PUSHR(reg) => format!("$$ R{reg}"),
+        Instruction::POP(reg) => format!("% R{reg}"),
+        Instruction::LDI(reg, immed) => format!("@ R{reg} {}", format_immed(immed)),
+        Instruction::CPY(a, b) => format!(": R{a} R{b}"),
+        Instruction::JMP(at) => format!("// {}", target(at)),
+        Instruction::JE(at) => format!("/= {}", target(at)),
+        Instruction::JNE(at) => format!("/! {}", target(at)),
+        Instruction::JG(at) => format!("/> {}", target(at)),
+        Instruction::JL(at) => format!("/< {}", target(at)),
+        Instruction::CMP(a, b) => format!("= R{a} R{b}"),
+        Instruction::ADD(a, b) => format!("+ R{a} R{b}"),
+        Instruction::SUB(a, b) => format!("- R{a} R{b}"),
+        Instruction::MUL(a, b) => format!("* R{a} R{b}"),
+        Instruction::DIV(a, b) => format!("/ R{a} R{b}"),
+        Instruction::AND(a, b) => format!("& R{a} R{b}"),
+        Instruction::OR(a, b) => format!("| R{a} R{b}"),
+        Instruction::XOR(a, b) => format!("^ R{a} R{b}"),
+        Instruction::SHR(reg, immed) => format!("> R{reg} {}", format_immed(immed)),
+        Instruction::SHL(reg, immed) => format!("< R{reg} {}", format_immed(immed)),
+        Instruction::HSTORE(addr) => format!("str {addr}"),
+        Instruction::HSTORER(reg) => format!("strR R{reg}"),
+        Instruction::HLOAD(addr) => format!("ld {addr}"),
+        Instruction::HLOADR(reg) => format!("ldR R{reg}"),
+        Instruction::CALL(at) => format!("call {}", target(at)),
+        Instruction::CALLR(reg) => format!("callR R{reg}"),
+        Instruction::RET() => "ret".to_string(),
+    }
+}
+
+// typed immediates look like u32$72 or i8$-5
+pub fn format_immed(immed: Immediate) -> String {
+    match immed {
+        Immediate::None() => "none$".to_string(),
+        Immediate::U8(i) => format!("u8${i}"),
+        Immediate::I8(i) => format!("i8${i}"),
+        Immediate::U16(i) => format!("u16${i}"),
+        Immediate::I16(i) => format!("i16${i}"),
+        Immediate::U32(i) => format!("u32${i}"),
+        Immediate::I32(i) => format!("i32${i}"),
+        Immediate::U64(i) => format!("u64${i}"),
+        Immediate::I64(i) => format!("i64${i}"),
+        // debug formatting keeps enough digits to parse back to the same float
+        Immediate::F32(i) => format!("f32${i:?}"),
+        Immediate::F64(i) => format!("f64${i:?}"),
+    }
+}
+
+/*
+    prints the program as ArtOfASM that assembles back to the same program, e.g.
+
+    ; data, loaded into the heap starting at address 0
+    /* heap      0 */ .string "Hello, world!\n"
+
+    ; code
+    /*      0 | 03 06 00 00 00 00 00 00 00 00 */ $ u64$0  ; PUSH at hello_world.artofasm:7
+    /*     10 | 02 00 00 00 00                */ int 0  ; INT at hello_world.artofasm:8
+
+    the address & raw bytes of every instruction are kept in a comment before it,
+    & where in the source it came from after it if the program has debug info
+*/
+pub fn disassemble(program: &Program) -> String {
+    let mut out = String::new();
+
+    if !program.data.is_empty() {
+        out.push_str("; data, loaded into the heap starting at address 0\n");
+
+        let data_syms: Vec<&Symbol> = program.symbols.iter().filter(|sym| sym.is_data).collect();
+        let breaks: Vec<Address> = data_syms.iter().map(|sym| sym.addr).collect();
+
+        for (addr, directive) in data_directives(&program.data, &breaks) {
+            for sym in data_syms.iter().filter(|sym| sym.addr == addr) {
+                writeln!(out, ".{}", sym.name).unwrap();
+            }
+
+            writeln!(out, "/* heap {addr:>6} */ {directive}").unwrap();
+        }
+
+        out.push('\n');
+    }
+
+    let mut lines = decode_all(&program.code);
+    let mut lbls = labels(&lines, &program.symbols);
+
+    if program.entry != 0 {
+        let entry = match lines.iter().any(|l| l.addr == program.entry) {
+            true => lbls.entry(program.entry).or_insert_with(|| format!("lbl_{}", program.entry)).clone(),
+            _ => program.entry.to_string(),
+        };
+
+        writeln!(out, ".entry {entry}\n").unwrap();
+    }
+
+    // the assembler ends every program with a HLT, which it'd add again if it was disassembled as an instruction
+    let appended_hlt = match lines.last() {
+        Some(Line { instr: Some(Instruction::HLT()), .. }) => lines.pop(),
+        _ => None,
+    };
+
+    let width = lines.iter().chain(&appended_hlt).map(|l| l.bytes.len()*3 - 1).max().unwrap_or(0);
+    out.push_str("; code\n");
+
+    for line in lines.iter().chain(&appended_hlt) {
+        let syms: Vec<&str> = program.symbols.iter()
+            .filter(|sym| !sym.is_data && sym.addr == line.addr)
+            .map(|sym| sym.name.as_str())
+            .collect();
+
+        match lbls.get(&line.addr) {
+            _ if !syms.is_empty() => for sym in syms {
+                writeln!(out, ".{sym}").unwrap();
+            },
+            Some(lbl) => writeln!(out, ".{lbl}").unwrap(),
+            _ => {},
+        }
+
+        let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{b:02x}")).collect();
+        write!(out, "/* {:>6} | {:<width$} */ ", line.addr, bytes.join(" ")).unwrap();
+
+        match line.instr {
+            _ if appended_hlt.as_ref() == Some(line) => out.push_str("; hlt, appended by the assembler\n"),
+            Some(instr) => match program.debug.location(line.addr) {
+                Some(loc) => writeln!(out, "{}  ; {} at {loc}", format_instr(instr, &lbls), instr_name(instr)).unwrap(),
+                _ => writeln!(out, "{}  ; {}", format_instr(instr, &lbls), instr_name(instr)).unwrap(),
+            },
+            _ if line.bytes.len() == 1 => writeln!(out, "; unknown opcode {}", line.bytes[0]).unwrap(),
+            _ => out.push_str("; cut off by the end of the program\n"),
+        }
+    }
+
+    out
+}
+
+// the Instruction variant's name, e.g. PUSH
+pub fn instr_name(instr: Instruction) -> String {
+    let debug = format!("{instr:?}");
+    debug[..debug.find('(').unwrap_or(debug.len())].to_string()
+}
+
+// groups runs of same typed values into directives, null terminated runs of printable U32 chars become .strings
+// a new directive is started at every address in breaks so that labels can be put before it
+fn data_directives(data: &[Immediate], breaks: &[Address]) -> Vec<(Address, String)> {
+    let mut directives = vec![];
+    let mut i = 0;
+
+    while i < data.len() {
+        if let Some(s) = string_at(&data[i..]) {
+            let len = s.chars().count()+1;
+
+            if !breaks.iter().any(|at| (i+1..i+len).contains(at)) {
+                directives.push((i, format!(".string \"{}\"", escape(&s))));
+                i += len;
+                continue;
+            }
+        }
+
+        let int_type = format_immed(data[i]).split_once('$').map(|(t, _)| t.to_string()).unwrap_or_default();
+        let start = i;
+        let mut vals = vec![];
+
+        while i < data.len() && vals.len() < DATA_PER_LINE && same_type(data[start], data[i]) {
+            if !vals.is_empty() && (breaks.contains(&i) || string_at(&data[i..]).is_some()) {
+                break;
+            }
+
+            vals.push(format_immed(data[i]).split_once('$').map(|(_, v)| v.to_string()).unwrap_or_default());
+            i += 1;
+        }
+
+        directives.push((start, format!(".{int_type} {}", vals.join(", "))));
+    }
+
+    directives
+}
+
+fn string_at(data: &[Immediate]) -> Option<String> {
+    let mut s = String::new();
+
+    for immed in data {
+        match immed {
+            Immediate::U32(0) if !s.is_empty() => return Some(s),
+            Immediate::U32(c) => match char::from_u32(*c) {
+                Some(c) if !c.is_control() || matches!(c, '\n'|'\r'|'\t') => s.push(c),
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+fn escape(s: &str) -> String {
+    s.chars().map(|c| match c {
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\t' => "\\t".to_string(),
+        '\\' => "\\\\".to_string(),
+        '"' => "\\\"".to_string(),
+        c => c.to_string(),
+    }).collect()
+}
+
+fn same_type(a: Immediate, b: Immediate) -> bool {
+    std::mem::discriminant(&a) == std::mem::discriminant(&b)
+}
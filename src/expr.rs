@@ -0,0 +1,200 @@
+use {
+    crate::lexer::unescape,
+    std::fmt,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprError {
+    Unknown(String),                     // a name that isn't defined (yet)
+    Invalid(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::Unknown(name) => write!(f, "unknown label {name:?}"),
+            ExprError::Invalid(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+/*
+    assemble time arithmetic on integer literals & names, e.g. BUF+4, SIZE*2 or (label_end-label_start)/4
+
+    expr  -> term (('+' | '-') term)*
+    term  -> unary (('*' | '/') unary)*
+    unary -> '-' unary | atom
+    atom  -> integer literal | name | '(' expr ')'
+*/
+pub fn eval(src: &str, resolve: &mut dyn FnMut(&str) -> Result<i128, ExprError>) -> Result<i128, ExprError> {
+    let mut parser = Parser { src, chars: src.chars().collect(), i: 0, resolve };
+    let n = parser.expr()?;
+
+    match parser.chars.get(parser.i) {
+        Some(c) => Err(parser.invalid(format!("unexpected {c:?}"))),
+        _ => Ok(n),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    chars: Vec<char>,
+    i: usize,
+    resolve: &'a mut dyn FnMut(&str) -> Result<i128, ExprError>,
+}
+
+impl Parser<'_> {
+    fn expr(&mut self) -> Result<i128, ExprError> {
+        let mut n = self.term()?;
+
+        loop {
+            n = match self.chars.get(self.i) {
+                Some('+') => {
+                    self.i += 1;
+                    let rhs = self.term()?;
+                    self.checked(n.checked_add(rhs))?
+                },
+                Some('-') => {
+                    self.i += 1;
+                    let rhs = self.term()?;
+                    self.checked(n.checked_sub(rhs))?
+                },
+                _ => return Ok(n),
+            };
+        }
+    }
+
+    fn term(&mut self) -> Result<i128, ExprError> {
+        let mut n = self.unary()?;
+
+        loop {
+            n = match self.chars.get(self.i) {
+                Some('*') => {
+                    self.i += 1;
+                    let rhs = self.unary()?;
+                    self.checked(n.checked_mul(rhs))?
+                },
+                Some('/') => {
+                    self.i += 1;
+                    let rhs = self.unary()?;
+
+                    if rhs == 0 {
+                        return Err(self.invalid("division by zero".to_string()));
+                    }
+
+                    self.checked(n.checked_div(rhs))?
+                },
+                _ => return Ok(n),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i128, ExprError> {
+        if self.chars.get(self.i) == Some(&'-') {
+            self.i += 1;
+            let n = self.unary()?;
+            return self.checked(n.checked_neg());
+        }
+
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<i128, ExprError> {
+        let start = self.i;
+
+        match self.chars.get(self.i) {
+            Some('(') => {
+                self.i += 1;
+                let n = self.expr()?;
+
+                if self.chars.get(self.i) != Some(&')') {
+                    return Err(self.invalid("expected )".to_string()));
+                }
+
+                self.i += 1;
+                Ok(n)
+            },
+            Some('\'') => {
+                self.i += 1;
+
+                while self.i < self.chars.len() && self.chars[self.i] != '\'' {
+                    if self.chars[self.i] == '\\' {
+                        self.i += 1;
+                    }
+
+                    self.i += 1;
+                }
+
+                self.i = (self.i + 1).min(self.chars.len());
+                parse_int(&self.slice(start)).map_err(ExprError::Invalid)
+            },
+            Some(c) if c.is_alphanumeric() || *c == '_' => {
+                let is_num = c.is_ascii_digit();
+
+                while self.i < self.chars.len() && (self.chars[self.i].is_alphanumeric() || self.chars[self.i] == '_') {
+                    self.i += 1;
+                }
+
+                let word = self.slice(start);
+                if is_num {
+                    parse_int(&word).map_err(ExprError::Invalid)
+                } else {
+                    (self.resolve)(&word)
+                }
+            },
+            Some(c) => Err(self.invalid(format!("unexpected {c:?}"))),
+            _ => Err(self.invalid("expected a value".to_string())),
+        }
+    }
+
+    fn slice(&self, start: usize) -> String {
+        self.chars[start..self.i].iter().collect()
+    }
+
+    fn checked(&self, n: Option<i128>) -> Result<i128, ExprError> {
+        n.ok_or_else(|| self.invalid("overflowed".to_string()))
+    }
+
+    fn invalid(&self, msg: String) -> ExprError {
+        ExprError::Invalid(format!("{msg} in {:?}", self.src))
+    }
+}
+
+// integer literals are decimal, 0x hex, 0b binary or 0o octal with optional _ separators, or a 'c'har
+// parsed wide so that the caller can range check them against the type they're meant for
+pub fn parse_int(s: &str) -> Result<i128, String> {
+    let (is_neg, lit) = match s.strip_prefix('-') {
+        Some(lit) => (true, lit),
+        _ => (false, s),
+    };
+
+    let n = if let Some(c) = lit.strip_prefix('\'').and_then(|c| c.strip_suffix('\'')).filter(|_| lit.len() > 1) {
+        let c = unescape(c)?;
+        let mut chars = c.chars();
+
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => c as i128,
+            _ => return Err(format!("char literal {lit} must hold exactly one char")),
+        }
+    } else {
+        let (radix, digits) = match lit.get(..2) {
+            Some("0x"|"0X") => (16, &lit[2..]),
+            Some("0b"|"0B") => (2, &lit[2..]),
+            Some("0o"|"0O") => (8, &lit[2..]),
+            _ => (10, lit),
+        };
+
+        let digits = digits.replace('_', "");
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(format!("{s:?} isn't a valid integer"));
+        }
+
+        // every char is a digit so this can only fail by overflowing
+        match i128::from_str_radix(&digits, radix) {
+            Ok(n) => n,
+            _ => return Err(format!("{s} is too large")),
+        }
+    };
+
+    Ok(if is_neg { -n } else { n })
+}
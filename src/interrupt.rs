@@ -0,0 +1,373 @@
+use {
+    crate::vm::{Address, Immediate, VmErrorKind, REGISTER_COUNT},
+    std::{char, fs::{self, read_to_string}, io::{BufRead, BufReader, Read, Write}},
+};
+
+pub const INT_WRITE: Address = 0;
+pub const INT_HEAP_ALLOC: Address = 1;
+pub const INT_READ_FILE: Address = 2;
+pub const INT_WRITE_FILE: Address = 3;
+pub const INT_PANIC: Address = 4;
+pub const INT_READ_LINE: Address = 5;
+
+// the parts of the VM's state an interrupt handler may look at and modify
+pub struct InterruptContext<'a> {
+    pub stack: &'a mut Vec<Immediate>,
+    pub reg: &'a mut [Immediate; REGISTER_COUNT],
+    pub heap: &'a mut Vec<Immediate>,
+    pub heap_limit: usize,               // cells the heap may grow to, see VirtualMachine::set_heap_limit
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+    pub stdin: &'a mut dyn Read,
+}
+
+// handlers are registered with VirtualMachine::register_interrupt, any FnMut(&mut InterruptContext) works as one
+pub trait InterruptHandler {
+    fn handle(&mut self, ctx: &mut InterruptContext) -> Result<(), VmErrorKind>;
+}
+
+impl<F: FnMut(&mut InterruptContext) -> Result<(), VmErrorKind>> InterruptHandler for F {
+    fn handle(&mut self, ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+        self(ctx)
+    }
+}
+
+impl InterruptContext<'_> {
+    pub fn push(&mut self, immed: Immediate) {
+        self.stack.push(immed);
+    }
+
+    pub fn pop(&mut self) -> Result<Immediate, VmErrorKind> {
+        self.stack.pop().ok_or(VmErrorKind::StackUnderflow)
+    }
+
+    pub fn pop_addr(&mut self) -> Result<Address, VmErrorKind> {
+        let immed = self.pop()?;
+        as_addr(immed)
+    }
+
+    pub fn heap_get(&self, addr: Address) -> Result<Immediate, VmErrorKind> {
+        self.heap.get(addr).copied().ok_or(VmErrorKind::HeapOutOfBounds(addr))
+    }
+
+    pub fn heap_set(&mut self, addr: Address, immed: Immediate) -> Result<(), VmErrorKind> {
+        match self.heap.get_mut(addr) {
+            Some(cell) => {
+                *cell = immed;
+                Ok(())
+            },
+            _ => Err(VmErrorKind::HeapOutOfBounds(addr)),
+        }
+    }
+
+    // reads a null terminated (U32 0) string of U32 unicode chars starting at addr
+    pub fn read_str(&self, mut addr: Address) -> Result<String, VmErrorKind> {
+        let mut buf = String::new();
+
+        loop {
+            match self.heap_get(addr)? {
+                Immediate::U32(0) => return Ok(buf),
+                Immediate::U32(i) => buf.push(char::from_u32(i).ok_or(VmErrorKind::InvalidChar(Immediate::U32(i)))?),
+                i => return Err(VmErrorKind::InvalidChar(i)),
+            }
+
+            addr += 1;
+        }
+    }
+
+    // writes s as a null terminated string of U32 unicode chars into a freshly allocated heap region
+    pub fn alloc_str(&mut self, s: &str) -> Result<Address, VmErrorKind> {
+        let len = s.chars().count();
+        let addr = self.heap_alloc(len+1)?;
+
+        for (i, ch) in s.chars().enumerate() {
+            self.heap[addr+i] = Immediate::U32(ch as u32);
+        }
+
+        self.heap[addr+len] = Immediate::U32(0);
+        Ok(addr)
+    }
+
+    // zeroes out first available heap region & returns ptr to it, expanding the heap if none were found
+    // & it can grow by to_alloc cells without going past the heap limit, 0 cells can't be allocated as there'd be no cell to point to
+    pub fn heap_alloc(&mut self, to_alloc: usize) -> Result<Address, VmErrorKind> {
+        if to_alloc == 0 {
+            return Err(VmErrorKind::ZeroSizedAlloc);
+        }
+
+        let mut curr_free = 0;
+        let mut ptr: Option<usize> = None;
+
+        for (i, cell) in self.heap.iter().enumerate() {
+            if let Immediate::None() = cell {
+                curr_free += 1;
+                if curr_free >= to_alloc {
+                    ptr = Some(((i as isize - to_alloc as isize) + 1) as usize);
+                    break;
+                }
+            } else {
+                curr_free = 0;
+            }
+        }
+
+        let addr = match ptr {
+            Some(addr) => addr,
+            // free cells at the end of the heap are the start of the region
+            _ => {
+                let addr = self.heap.len() - curr_free;
+                let len = addr.checked_add(to_alloc)
+                    .filter(|len| *len <= self.heap_limit)
+                    .ok_or(VmErrorKind::HeapExhausted(to_alloc))?;
+
+                self.heap.resize(len, Immediate::None());
+                addr
+            },
+        };
+
+        for i in addr..addr+to_alloc {
+            self.heap[i] = Immediate::U8(0);
+        }
+
+        Ok(addr)
+    }
+}
+
+pub fn as_addr(immed: Immediate) -> Result<Address, VmErrorKind> {
+    match immed {
+        Immediate::U8(i) => Ok(i as Address),
+        Immediate::U16(i) => Ok(i as Address),
+        Immediate::U32(i) => Ok(i as Address),
+        Immediate::U64(i) => Ok(i as Address),
+        _ => Err(VmErrorKind::InvalidAddress(immed)),
+    }
+}
+
+// every handler the VM comes with, registered by VirtualMachine::new
+pub fn builtins() -> Vec<(Address, Box<dyn InterruptHandler>)> {
+    vec![
+        (INT_WRITE, Box::new(write)),
+        (INT_HEAP_ALLOC, Box::new(heap_alloc)),
+        (INT_READ_FILE, Box::new(read_file)),
+        (INT_WRITE_FILE, Box::new(write_file)),
+        (INT_PANIC, Box::new(panic)),
+        (INT_READ_LINE, Box::new(read_line)),
+    ]
+}
+
+// the name of a built-in interrupt, e.g. WRITE
+pub fn builtin_name(int: Address) -> Option<&'static str> {
+    match int {
+        INT_WRITE => Some("WRITE"),
+        INT_HEAP_ALLOC => Some("HEAP_ALLOC"),
+        INT_READ_FILE => Some("READ_FILE"),
+        INT_WRITE_FILE => Some("WRITE_FILE"),
+        INT_PANIC => Some("PANIC"),
+        INT_READ_LINE => Some("READ_LINE"),
+        _ => None,
+    }
+}
+
+/*
+    WRITE interrupt
+    params:
+        start ptr to buf (u8/u16/u32/u64)
+    desc:
+        writes buf to stdout
+
+example printing 'A':
+
+push 65 ('A' in unicode)
+str 0
+push 0
+int 0
+*/
+fn write(ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+    let addr = ctx.pop_addr()?;
+    let buf = ctx.read_str(addr)?;
+
+    write!(ctx.stdout, "{buf}").map_err(|e| VmErrorKind::Io(e.to_string()))?;
+    ctx.stdout.flush().map_err(|e| VmErrorKind::Io(e.to_string()))
+}
+
+/*
+    HEAP_ALLOC interrupt
+    params:
+        requested alloc size (in immediates, as u8/u16/u32/u64)
+    desc:
+        zeroes out first available heap region & returns ptr to it,
+        if no available heap regions were found, expands the heap with 0s and returns ptr to it
+        faults if the heap would grow past its limit (see VirtualMachine::set_heap_limit) or if 0 immediates were requested
+
+example allocating string 'A':
+
+push 0 ('\0' or null terminator in unicode)
+push 65 ('A' in unicode)
+
+push 2
+int 1
+
+pop R1
+ldi R3 1
+add R1 R3
+pop R2
+
+strR R1
+strR R2
+*/
+fn heap_alloc(ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+    let to_alloc = ctx.pop_addr()?;
+    let addr = ctx.heap_alloc(to_alloc)?;
+
+    ctx.push(Immediate::U64(addr as u64));
+    Ok(())
+}
+
+/*
+    READ_FILE interrupt
+    params:
+        start ptr to file path (u8/u16/u32/u64)
+    desc:
+        pushes ptr to buffer in heap then a 1 if successful (1 would be at the top of the stack)
+        pushes two 0s if unsucessful (err happened)
+
+example reading 'A.txt':
+
+push 0 ('\0' or null terminator in unicode)
+push 116 ('t' in unicode)
+push 120 ('x' in unicode)
+push 116 ('t' in unicode)
+push 46 ('.' in unicode)
+push 65 ('A' in unicode)
+
+str 0
+str 1
+str 2
+str 3
+str 4
+str 5
+
+push 0
+int 2
+*/
+fn read_file(ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+    let addr = ctx.pop_addr()?;
+    let path = ctx.read_str(addr)?;
+
+    match read_to_string(path) {
+        Ok(s) => {
+            let buf_start = ctx.alloc_str(&s)?;
+            ctx.stack.extend_from_slice(&[Immediate::U64(buf_start as u64), Immediate::U8(1)]);
+        },
+        _ => ctx.stack.extend_from_slice(&[Immediate::U64(0), Immediate::U8(0)]),
+    }
+
+    Ok(())
+}
+
+/*
+    WRITE_FILE interrupt
+    params:
+        start ptr to buf (u8/u16/u32/u64) (first arg)
+        start ptr to file path (u8/u16/u32/u64)
+    desc:
+        attempts to write to file or create file if nonexistant with buf, pushes 0 if err, 1 if success
+
+example writing 'A' to 'A.txt':
+
+push 0 ('\0' or null terminator in unicode)
+push 116 ('t' in unicode)
+push 120 ('x' in unicode)
+push 116 ('t' in unicode)
+push 46 ('.' in unicode)
+push 65 ('A' in unicode)
+str 0
+str 1
+str 2
+str 3
+str 4
+str 5
+
+push 0 ('\0' or null terminator in unicode)
+push 65 ('A' in unicode)
+str 6
+str 7
+
+push 0
+push 6
+int 3
+*/
+fn write_file(ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+    let buf_addr = ctx.pop_addr()?;
+    let buf = ctx.read_str(buf_addr)?;
+
+    let path_addr = ctx.pop_addr()?;
+    let path = ctx.read_str(path_addr)?;
+
+    match fs::write(path, buf) {
+        Ok(_) => ctx.push(Immediate::U8(1)),
+        _ => ctx.push(Immediate::U8(0)),
+    }
+
+    Ok(())
+}
+
+/*
+    PANIC interrupt
+    params:
+        start ptr to panic message (u64)
+    desc:
+        prints out panic message to stderr then stops execution with a VmErrorKind::Panic holding it
+
+    example panicking with 'A':
+
+    push 0 ('\0' or null terminator in unicode)
+    push 65 ('A' in unicode)
+    
+    str 0
+    str 1
+
+    push 0
+    int 4
+*/
+fn panic(ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+    let addr = ctx.pop_addr()?;
+    let buf = ctx.read_str(addr)?;
+
+    writeln!(ctx.stderr, "panicked with err message:\n{buf}").map_err(|e| VmErrorKind::Io(e.to_string()))?;
+    Err(VmErrorKind::Panic(buf))
+}
+
+/*
+    READ_LINE interrupt
+    params:
+        none
+    desc:
+        reads a line (without the line break) from stdin into a new heap buffer,
+        pushes ptr to buffer in heap then a 1 if successful (1 would be at the top of the stack)
+        pushes two 0s if unsucessful (err happened or stdin has ended)
+
+example echoing a line:
+
+int 5
+pop R1
+pop R2
+pushr R2
+int 0
+*/
+fn read_line(ctx: &mut InterruptContext) -> Result<(), VmErrorKind> {
+    let mut line = String::new();
+
+    // one byte at a time so that nothing past the line break is taken out of stdin
+    match BufReader::with_capacity(1, &mut *ctx.stdin).read_line(&mut line) {
+        Ok(n) if n > 0 => {
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+
+            let buf_start = ctx.alloc_str(line)?;
+            ctx.stack.extend_from_slice(&[Immediate::U64(buf_start as u64), Immediate::U8(1)]);
+        },
+        _ => ctx.stack.extend_from_slice(&[Immediate::U64(0), Immediate::U8(0)]),
+    }
+
+    Ok(())
+}
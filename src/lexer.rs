@@ -0,0 +1,208 @@
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub line: usize,                     // 1 based
+    pub col: usize,                      // 1 based, in chars
+    pub expansion: usize,                // 0 if straight from the source, otherwise the macro expansion it was produced by
+    pub file: usize,                     // index of the source file the token is from, see Assembler::files
+}
+
+impl Token {
+    // operands have to be on the same line as their instr, tokens from different macro expansions or files never are
+    pub fn same_line(&self, other: &Token) -> bool {
+        self.line == other.line && self.expansion == other.expansion && self.file == other.file
+    }
+}
+
+// kept so that tools such as formatters & listing generators can put comments back where they were
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,                    // including the ; or /* */ delimiters
+    pub line: usize,
+    pub col: usize,
+    pub is_block: bool,
+    pub file: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Lexed {
+    pub toks: Vec<Token>,
+    pub comments: Vec<Comment>,
+    pub errs: Vec<LexError>,
+}
+
+/*
+    tokens are separated by whitespace, except for
+    commas, which are always tokens of their own
+    "string literals", which are single tokens (quotes included) that may contain whitespace
+    'c'har literals, which may contain whitespace & commas and stay part of the token they're in, e.g. u8$' '
+
+    comments are either
+    ; line comments, running until the end of the line
+    /* block comments */, which may span multiple lines
+*/
+pub fn tokenize(src: &str) -> Lexed {
+    let src: Vec<char> = src.chars().collect();
+    let mut lexed = Lexed::default();
+    let mut curr: Option<Token> = None;
+
+    let mut i = 0;
+    let mut line = 1;
+    let mut col = 1;
+
+    while i < src.len() {
+        let ch = src[i];
+
+        if matches!(ch, ' '|'\t'|'\r'|'\n') {
+            lexed.toks.extend(curr.take());
+
+            if ch == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+
+            i += 1;
+            continue;
+        }
+
+        if ch == ';' {
+            lexed.toks.extend(curr.take());
+
+            let start = i;
+            while i < src.len() && src[i] != '\n' {
+                i += 1;
+            }
+
+            lexed.comments.push(Comment { text: src[start..i].iter().collect(), line, col, is_block: false, file: 0 });
+            col += i-start;
+            continue;
+        }
+
+        if ch == '/' && src.get(i+1) == Some(&'*') {
+            lexed.toks.extend(curr.take());
+
+            let (start, start_line, start_col) = (i, line, col);
+            i += 2;
+            col += 2;
+
+            while i < src.len() && !(src[i] == '*' && src.get(i+1) == Some(&'/')) {
+                if src[i] == '\n' {
+                    line += 1;
+                    col = 1;
+                } else {
+                    col += 1;
+                }
+
+                i += 1;
+            }
+
+            if i >= src.len() {
+                lexed.errs.push(LexError { line: start_line, col: start_col, msg: "unterminated block comment".to_string() });
+            } else {
+                i += 2;
+                col += 2;
+            }
+
+            lexed.comments.push(Comment { text: src[start..i].iter().collect(), line: start_line, col: start_col, is_block: true, file: 0 });
+            continue;
+        }
+
+        if ch == ',' {
+            lexed.toks.extend(curr.take());
+            lexed.toks.push(Token { text: ",".to_string(), line, col, expansion: 0, file: 0 });
+
+            i += 1;
+            col += 1;
+            continue;
+        }
+
+        if ch == '"' && curr.is_none() {
+            let start = i;
+            i += 1;
+
+            while i < src.len() && !matches!(src[i], '"'|'\n') {
+                if src[i] == '\\' && i+1 < src.len() && src[i+1] != '\n' {
+                    i += 1;
+                }
+
+                i += 1;
+            }
+
+            if src.get(i) == Some(&'"') {
+                i += 1;
+            } else {
+                lexed.errs.push(LexError { line, col, msg: "unterminated string literal".to_string() });
+            }
+
+            lexed.toks.push(Token { text: src[start..i].iter().collect(), line, col, expansion: 0, file: 0 });
+            col += i-start;
+            continue;
+        }
+
+        if ch == '\'' {
+            let start = i;
+            i += 1;
+
+            while i < src.len() && !matches!(src[i], '\''|'\n') {
+                if src[i] == '\\' && i+1 < src.len() && src[i+1] != '\n' {
+                    i += 1;
+                }
+
+                i += 1;
+            }
+
+            if src.get(i) == Some(&'\'') {
+                i += 1;
+            } else {
+                lexed.errs.push(LexError { line, col, msg: "unterminated char literal".to_string() });
+            }
+
+            curr.get_or_insert_with(|| Token { text: String::new(), line, col, expansion: 0, file: 0 }).text.extend(&src[start..i]);
+            col += i-start;
+            continue;
+        }
+
+        curr.get_or_insert_with(|| Token { text: String::new(), line, col, expansion: 0, file: 0 }).text.push(ch);
+        i += 1;
+        col += 1;
+    }
+
+    lexed.toks.extend(curr);
+    lexed
+}
+
+// resolves the \n \r \t \0 \\ \" & \' escapes in the inside of a string literal
+pub fn unescape(s: &str) -> Result<String, String> {
+    let mut buf = String::new();
+    let mut chars = s.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            buf.push(ch);
+            continue;
+        }
+
+        buf.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some(c) => return Err(format!("unknown escape \\{c}")),
+            _ => return Err("expected escape after \\".to_string()),
+        });
+    }
+
+    Ok(buf)
+}
@@ -1,2 +1,13 @@
 pub mod vm;
-pub mod assembler;
\ No newline at end of file
+pub mod assembler;
+pub mod lexer;
+pub mod expr;
+pub mod program;
+pub mod interrupt;
+pub mod disassembler;
+pub mod verifier;
+pub mod debugger;
+
+pub mod trace;
+pub mod profiler;
+pub mod coverage;
\ No newline at end of file
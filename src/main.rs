@@ -1,9 +1,13 @@
 use {
     art_of_vm::{
-        assembler::Assembler, vm::VirtualMachine
+        assembler::{Assembler, Source}, coverage::Coverage, debugger::{Command, Debugger}, disassembler::disassemble,
+        profiler::Profile, program::{DebugInfo, Program}, trace::{JsonTracer, TextTracer, TraceEvent, Tracer}, verifier::verify,
+        vm::{VirtualMachine, VmError, VmErrorKind},
+    },
+    std::{
+        cell::RefCell, env::args, fs::{self, read_to_string}, io::{self, BufRead, Write}, path::{Path, PathBuf}, process::exit, rc::Rc,
+        time::{Duration, Instant},
     },
-    bincode::{deserialize, serialize},
-    std::{env::args, fs::{self, read_to_string, File}, io::Read, path::Path, time::{Duration, Instant}},
 };
 
 const DEFAULT_HEAP_SIZE: usize = 1024; // (bytes)
@@ -12,7 +16,47 @@ const BENCHMARK_ATTEMPTS: usize = 1000;
 #[inline(always)]
 #[cold]
 fn usage(exe: String) -> ! {
-    panic!("usage: {exe} (exe|assemble|benchmark|dbg) (file) (out_file [if using 'assemble'])");
+    panic!(
+        "usage: {exe} (exe|benchmark|dbg|debug) (file) | {exe} exe [--trace[=text|json]] [--trace-out trace_file] [--coverage lcov_file] (file) \
+        | {exe} profile [--folded folded_file] (file) \
+        | {exe} assemble [-I include_dir]... (file)... (out_file)"
+    );
+}
+
+fn load(path: &str) -> Program {
+    let buf = fs::read(path).unwrap_or_else(|_| panic!("unable to read file {path:?}"));
+
+    match Program::from_bytes(&buf) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            exit(1);
+        },
+    }
+}
+
+// loads a program that's about to be run, bad bytecode is reported instead of run
+fn load_verified(path: &str) -> Program {
+    let program = load(path);
+
+    if let Err(errs) = verify(&program.code, program.entry) {
+        for e in errs.iter() {
+            eprintln!("{path}: {e}");
+        }
+
+        eprintln!("refusing to run {path:?} due to {} error(s)", errs.len());
+        exit(1);
+    }
+
+    program
+}
+
+// prefixes the fault with where in the source it happened, if the program knows
+fn report(debug: &DebugInfo, e: &VmError) {
+    match debug.location(e.instr_ptr) {
+        Some(loc) => eprintln!("{loc}: {e}"),
+        _ => eprintln!("{e}"),
+    }
 }
 
 fn main() {
@@ -26,49 +70,146 @@ fn main() {
 
     match args[0].as_str() {
         "exe" => {
-            let mut file = File::open(args[1].as_str())
-                .expect(format!("unable to read file {:?}", args[1]).as_str());
-            let mut buf: Vec<u8> = vec![];
-            file.read_to_end(&mut buf).unwrap();
+            // exe [--trace[=text|json]] [--trace-out trace_file] [--coverage lcov_file] (file), traces are written to stderr by default
+            let mut trace: Option<String> = None;
+            let mut trace_out: Option<String> = None;
+            let mut coverage_out: Option<String> = None;
+            let mut path: Option<String> = None;
+            let mut rest = args[1..].iter();
+
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--trace" => trace = Some("text".to_string()),
+                    "--trace-out" => trace_out = Some(rest.next().unwrap_or_else(|| usage(exe.clone())).clone()),
+                    "--coverage" => coverage_out = Some(rest.next().unwrap_or_else(|| usage(exe.clone())).clone()),
+                    _ if arg.starts_with("--trace=") => trace = Some(arg["--trace=".len()..].to_string()),
+                    _ if path.is_none() => path = Some(arg.clone()),
+                    _ => usage(exe),
+                }
+            }
+
+            let program = load_verified(&path.unwrap_or_else(|| usage(exe.clone())));
+
+            let mut tracers: Vec<Box<dyn Tracer>> = vec![];
+            let coverage = Rc::new(RefCell::new(Coverage::new(&program)));
+
+            if coverage_out.is_some() {
+                let recorded = coverage.clone();
+                tracers.push(Box::new(move |event: &TraceEvent| recorded.borrow_mut().record(event)));
+            }
+
+            if let Some(format) = trace {
+                let out: Box<dyn Write> = match trace_out {
+                    Some(path) => Box::new(fs::File::create(&path).unwrap_or_else(|_| panic!("unable to create file {path:?}"))),
+                    _ => Box::new(io::stderr()),
+                };
+                let out = io::BufWriter::new(out);
+
+                tracers.push(match format.as_str() {
+                    "text" => Box::new(TextTracer::new(out)),
+                    "json" => Box::new(JsonTracer::new(out)),
+                    _ => usage(exe),
+                });
+            }
+
+            let mut vm = VirtualMachine::from_program(program.clone(), DEFAULT_HEAP_SIZE);
+
+            if !tracers.is_empty() {
+                vm.set_tracer(Some(Box::new(move |event: &TraceEvent| {
+                    for tracer in tracers.iter_mut() {
+                        tracer.trace(event);
+                    }
+                })));
+            }
+
+            let run_t = Instant::now();
+            let res = vm.exec();
+            let took = run_t.elapsed();
+            // flushes the trace, which exit wouldn't
+            drop(vm);
+
+            // written even if the program faulted, to show how far it got
+            if let Some(path) = coverage_out {
+                let coverage = coverage.borrow();
+                fs::write(&path, coverage.lcov(&program)).unwrap_or_else(|_| panic!("unable to write file {path:?}"));
+                eprintln!("[{}, wrote lcov to {path:?}]", coverage.summary(&program));
+            }
 
-            let code: Vec<u8> = deserialize(&buf)
-                .expect("err deserializing given ArtOfVM machine code");
+            match res {
+                Ok(_) => println!("[exited successfully in {took:?}]"),
+                // the PANIC interrupt already wrote its message to stderr
+                Err(VmError { kind: VmErrorKind::Panic(_), .. }) => exit(1),
+                Err(e) => {
+                    report(&program.debug, &e);
+                    exit(1);
+                },
+            }
+        },
+        "profile" => {
+            // profile [--folded folded_file] (file), the folded stacks are written next to the file by default
+            let mut folded_out: Option<String> = None;
+            let mut path: Option<String> = None;
+            let mut rest = args[1..].iter();
+
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--folded" => folded_out = Some(rest.next().unwrap_or_else(|| usage(exe.clone())).clone()),
+                    _ if path.is_none() => path = Some(arg.clone()),
+                    _ => usage(exe),
+                }
+            }
+
+            let path = path.unwrap_or_else(|| usage(exe.clone()));
+            let program = load_verified(&path);
+
+            let mut vm = VirtualMachine::from_program(program.clone(), DEFAULT_HEAP_SIZE);
+
+            let profile = Rc::new(RefCell::new(Profile::new(&program)));
+            let recorded = profile.clone();
+            vm.set_tracer(Some(Box::new(move |event: &TraceEvent| recorded.borrow_mut().record(event))));
 
-            let mut vm = VirtualMachine::new(code, DEFAULT_HEAP_SIZE);
             let run_t = Instant::now();
-            vm.exec();
+            let res = vm.exec();
             let took = run_t.elapsed();
 
-            println!("[exited successfully in {took:?}]");
+            if let Err(e) = res {
+                report(&program.debug, &e);
+            }
+
+            let profile = profile.borrow();
+            println!("\n\nran for {took:?}, {}", profile.report(&program));
+
+            let folded_out = folded_out.unwrap_or_else(|| format!("{path}.folded"));
+            fs::write(&folded_out, profile.folded(&program)).unwrap_or_else(|_| panic!("unable to write file {folded_out:?}"));
+            println!("wrote folded stacks to {folded_out:?}");
         },
         "benchmark" => {
-            let mut file = File::open(args[1].as_str())
-                .expect(format!("unable to read file {:?}", args[1]).as_str());
-            let mut buf: Vec<u8> = vec![];
-            file.read_to_end(&mut buf).unwrap();
-
-            let code: Vec<u8> = deserialize(&buf)
-                .expect("err deserializing given ArtOfVM machine code");
+            let program = load_verified(&args[1]);
 
             let mut durs: Vec<Duration> = vec![];
 
             for _ in 0..BENCHMARK_ATTEMPTS {
-                let mut vm = VirtualMachine::new(code.clone(), DEFAULT_HEAP_SIZE);
+                let mut vm = VirtualMachine::from_program(program.clone(), DEFAULT_HEAP_SIZE);
                 let run_t = Instant::now();
-                vm.exec();
+                let res = vm.exec();
                 let took = run_t.elapsed();
 
+                if let Err(e) = res {
+                    report(&program.debug, &e);
+                    exit(1);
+                }
+
                 durs.push(took);
             }
 
             let mut ms: Vec<u128> = durs.iter().map(|d| d.as_micros()).collect();
             ms.sort_unstable();
 
-            let fast = ms.iter().min().unwrap().clone();
-            let slow = ms.iter().max().unwrap().clone();
+            let fast = *ms.iter().min().unwrap();
+            let slow = *ms.iter().max().unwrap();
 
             let median_i = ms.len() / 2;
-            let median = if ms.len() % 2 == 0 {
+            let median = if ms.len().is_multiple_of(2) {
                 (ms[median_i - 1] + ms[median_i]) / 2
             } else {
                 ms[median_i]
@@ -84,36 +225,88 @@ fn main() {
                 benchmark average (microseconds): {avg}"
             );
         },
-        "dbg" => {
-            let mut file = File::open(args[1].as_str())
-                .expect(format!("unable to read file {:?}", args[1]).as_str());
-            let mut buf: Vec<u8> = vec![];
-            file.read_to_end(&mut buf).unwrap();
+        "debug" => {
+            let mut debugger = Debugger::new(load_verified(&args[1]), DEFAULT_HEAP_SIZE);
+            println!("{}\n\ntype help for a list of commands", debugger.run(Command::List));
+
+            // an empty line repeats the last command
+            let mut last = Command::Step(1);
+            let mut stdin = io::stdin().lock();
+
+            loop {
+                print!("(debug) ");
+                io::stdout().flush().unwrap();
+
+                let mut line = String::new();
+                if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+
+                let cmd = match line.trim() {
+                    "" => last.clone(),
+                    line => match Command::parse(line) {
+                        Ok(cmd) => cmd,
+                        Err(e) => {
+                            println!("{e}");
+                            continue;
+                        },
+                    },
+                };
+
+                if cmd == Command::Quit {
+                    break;
+                }
 
-            let code: Vec<u8> = deserialize(&buf)
-                .expect("err deserializing given ArtOfVM machine code");
+                println!("{}", debugger.run(cmd.clone()));
+                last = cmd;
+            }
+        },
+        "dbg" => {
+            let program = load(&args[1]);
 
-            println!("machine code:\n{code:?}");
+            print!("{}", disassemble(&program));
         }
         "assemble" => {
-            let mut file = read_to_string(args[1].clone())
-                .expect(format!("unable to read file {:?}", args[1]).as_str());
-            file.push('\0');
+            // assemble [-I include_dir]... (file)... (out_file), the files share one label namespace
+            let mut include_dirs: Vec<PathBuf> = vec![];
+            let mut paths: Vec<String> = vec![];
+            let mut rest = args[1..].iter();
 
-            if args.len() != 3 {
-                usage(exe);
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-I" => include_dirs.push(PathBuf::from(rest.next().unwrap_or_else(|| usage(exe.clone())))),
+                    _ => paths.push(arg.clone()),
+                }
             }
 
-            let out_file = args[2].clone();
+            let out_file = match paths.pop() {
+                Some(out_file) if !paths.is_empty() => out_file,
+                _ => usage(exe),
+            };
 
-            let mut assembler = Assembler::new(file);
+            let sources = paths.iter().map(|path| Source {
+                path: PathBuf::from(path),
+                text: read_to_string(path).unwrap_or_else(|_| panic!("unable to read file {path:?}")),
+            }).collect();
+
+            let mut assembler = Assembler::from_sources(sources, &include_dirs);
 
             let assemble_t = Instant::now();
-            let assembled = assembler.assemble();
+            let assembled = match assembler.assemble() {
+                Ok(program) => program,
+                Err(diags) => {
+                    for diag in diags.iter() {
+                        eprintln!("{diag}\n");
+                    }
+
+                    eprintln!("failed to assemble {paths:?} due to {} error(s)", diags.len());
+                    exit(1);
+                },
+            };
             let took = assemble_t.elapsed();
 
             println!("took {took:?}");
-            fs::write(Path::new(&out_file), serialize(&assembled).unwrap()).unwrap();
+            fs::write(Path::new(&out_file), assembled.to_bytes()).unwrap();
             println!("wrote to {out_file:?}");
         },
         _ => usage(exe),
@@ -0,0 +1,188 @@
+use {
+    crate::{
+        disassembler::{decode_all, format_instr, instr_name, labels},
+        interrupt::builtin_name,
+        program::Program,
+        trace::TraceEvent,
+        vm::{decode, Address, Instruction},
+    },
+    std::{cmp::Reverse, collections::{BTreeMap, HashMap}, fmt::Write, time::Duration},
+};
+
+// how many of the most executed addresses the report lists
+const HOT_ADDRESSES: usize = 20;
+
+// what a program spent its time on, built up by record-ing the VM's trace events
+pub struct Profile {
+    counts: Vec<u64>,                    // times the instr starting at every bit position was executed
+    interrupts: BTreeMap<Address, (u64, Duration)>, // calls to & time spent in every interrupt
+    stacks: HashMap<Vec<Address>, u64>,  // instrs executed under every call stack, by where each call went
+    frames: Vec<Address>,
+    called: bool,                        // the last instr was a CALL(R), so the next one starts a frame
+}
+
+impl Profile {
+    pub fn new(program: &Program) -> Self {
+        Self {
+            counts: vec![0; program.code.len()],
+            interrupts: BTreeMap::new(),
+            stacks: HashMap::new(),
+            frames: vec![program.entry],
+            called: false,
+        }
+    }
+
+    // meant to be called from the VM's tracer, which times every INT's handler
+    pub fn record(&mut self, event: &TraceEvent) {
+        if let Instruction::INT(i) = event.instr {
+            let (calls, time) = self.interrupts.entry(i).or_default();
+            *calls += 1;
+            *time += event.interrupt_time.unwrap_or_default();
+        }
+
+        self.counts[event.instr_ptr] += 1;
+
+        if self.called {
+            self.frames.push(event.instr_ptr);
+            self.called = false;
+        }
+
+        match self.stacks.get_mut(self.frames.as_slice()) {
+            Some(n) => *n += 1,
+            _ => {
+                self.stacks.insert(self.frames.clone(), 1);
+            },
+        }
+
+        match event.instr {
+            Instruction::CALL(_) | Instruction::CALLR(_) => self.called = true,
+            Instruction::RET() if self.frames.len() > 1 => {
+                self.frames.pop();
+            },
+            _ => {},
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // every executed address & how many times it was executed, most executed first
+    pub fn by_address(&self) -> Vec<(Address, u64)> {
+        let mut counts: Vec<(Address, u64)> = self.counts.iter().copied().enumerate().filter(|(_, n)| *n > 0).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    // e.g. ("PUSH", 12), most executed first
+    pub fn by_opcode(&self, program: &Program) -> Vec<(String, u64)> {
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+
+        for (addr, n) in self.by_address() {
+            if let Some((instr, _)) = decode(&program.code, addr) {
+                *counts.entry(instr_name(instr)).or_default() += n;
+            }
+        }
+
+        sorted(counts)
+    }
+
+    // instrs executed between every code label & the next one, most executed first
+    pub fn by_label(&self, program: &Program) -> Vec<(String, u64)> {
+        let mut lbls: Vec<(Address, &str)> = vec![];
+        for sym in program.symbols.iter().filter(|sym| !sym.is_data) {
+            if !lbls.iter().any(|(addr, _)| *addr == sym.addr) {
+                lbls.push((sym.addr, &sym.name));
+            }
+        }
+        lbls.sort();
+
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+
+        for (addr, n) in self.by_address() {
+            let region = match lbls.partition_point(|(at, _)| *at <= addr) {
+                0 => "(no label)".to_string(),
+                i => lbls[i-1].1.to_string(),
+            };
+
+            *counts.entry(region).or_default() += n;
+        }
+
+        sorted(counts)
+    }
+
+    // every interrupt called, how many times & the time spent in it, most time first
+    pub fn interrupts(&self) -> Vec<(Address, u64, Duration)> {
+        let mut ints: Vec<(Address, u64, Duration)> = self.interrupts.iter().map(|(i, (calls, time))| (*i, *calls, *time)).collect();
+        ints.sort_by_key(|int| Reverse(int.2));
+        ints
+    }
+
+    /*
+        a line per call stack with the instrs executed under it, e.g.
+
+        entry 10
+        entry;twice 6
+
+        which is the folded stack format that flamegraph tools read, each function is named by the label it was called at
+        & the outermost one by the label at the entry point, if there is one
+    */
+    pub fn folded(&self, program: &Program) -> String {
+        let name = |(i, addr): (usize, &Address)| match (i, program.label_at(*addr)) {
+            (_, Some(lbl)) => lbl.to_string(),
+            (0, _) => "entry".to_string(),
+            _ => format!("lbl_{addr}"),
+        };
+
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(frames, n)| format!("{} {n}", frames.iter().enumerate().map(name).collect::<Vec<String>>().join(";")))
+            .collect();
+
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    pub fn report(&self, program: &Program) -> String {
+        let total = self.total();
+        let percent = |n: u64| n as f64 / total.max(1) as f64 * 100.0;
+        let mut out = String::new();
+
+        writeln!(out, "executed {total} instructions\n").unwrap();
+
+        out.push_str("by opcode\n");
+        for (op, n) in self.by_opcode(program) {
+            writeln!(out, "{n:>12} {:>6.2}%  {op}", percent(n)).unwrap();
+        }
+
+        out.push_str("\nby label\n");
+        for (lbl, n) in self.by_label(program) {
+            writeln!(out, "{n:>12} {:>6.2}%  {lbl}", percent(n)).unwrap();
+        }
+
+        let lbls = labels(&decode_all(&program.code), &program.symbols);
+
+        writeln!(out, "\nhottest addresses").unwrap();
+        for (addr, n) in self.by_address().into_iter().take(HOT_ADDRESSES) {
+            let asm = decode(&program.code, addr).map(|(instr, _)| format_instr(instr, &lbls)).unwrap_or_default();
+            writeln!(out, "{n:>12} {:>6.2}%  {addr:>6}  {asm}", percent(n)).unwrap();
+        }
+
+        let ints = self.interrupts();
+        if !ints.is_empty() {
+            out.push_str("\ninterrupts\n");
+
+            for (i, calls, time) in ints {
+                let name = builtin_name(i).map(|name| format!(" ({name})")).unwrap_or_default();
+                writeln!(out, "{calls:>12} calls {time:>12?}  int {i}{name}").unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+fn sorted(counts: BTreeMap<String, u64>) -> Vec<(String, u64)> {
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
@@ -0,0 +1,202 @@
+use {
+    crate::vm::{Address, Immediate},
+    bincode::{deserialize, serialize},
+    serde::{Deserialize, Serialize},
+    std::fmt,
+};
+
+pub const MAGIC: [u8; 4] = *b"AOVM";
+pub const FORMAT_VERSION: u16 = 1;
+
+// section kinds, sections of kinds a reader doesn't know about are skipped
+pub const SECTION_CODE: u8 = 1;
+pub const SECTION_DATA: u8 = 2;
+pub const SECTION_SYMBOLS: u8 = 3;
+pub const SECTION_DEBUG: u8 = 4;
+
+// kind, offset & len
+const SECTION_ENTRY_SIZE: usize = 1 + 4 + 4;
+
+// what the assembler produces & the VM runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub data: Vec<Immediate>,            // initialized data segment, loaded into the heap starting at address 0
+    pub entry: Address,                  // where in the code execution starts
+    pub symbols: Vec<Symbol>,
+    pub debug: DebugInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: Address,                   // bit position in the code, or heap address for data
+    pub is_data: bool,
+}
+
+// where in the source every instruction came from, empty for programs that weren't assembled from source
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<LineEntry>,           // sorted by addr
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineEntry {
+    pub addr: Address,                   // bit position of the start of the instruction
+    pub file: usize,                     // index into DebugInfo::files
+    pub line: usize,                     // 1 based
+    pub col: usize,                      // 1 based, in chars
+}
+
+impl DebugInfo {
+    pub fn line_at(&self, addr: Address) -> Option<&LineEntry> {
+        self.lines.binary_search_by_key(&addr, |l| l.addr).ok().map(|i| &self.lines[i])
+    }
+
+    // file:line of the instruction at addr, just the line for sources without a path
+    pub fn location(&self, addr: Address) -> Option<String> {
+        let entry = self.line_at(addr)?;
+
+        Some(match self.files.get(entry.file).map(String::as_str) {
+            Some("") | None => format!("line {}", entry.line),
+            Some(file) => format!("{file}:{}", entry.line),
+        })
+    }
+}
+
+impl Program {
+    // name of the first code label defined at addr
+    pub fn label_at(&self, addr: Address) -> Option<&str> {
+        self.symbols.iter().find(|sym| !sym.is_data && sym.addr == addr).map(|sym| sym.name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContainerError {
+    BadMagic,                            // not an .aovm file at all
+    UnsupportedVersion(u16),             // written by a newer (or older, incompatible) version of ArtOfVM
+    Truncated,                           // the header or a section runs past the end of the file
+    MissingSection(&'static str),
+    Malformed(String),
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::BadMagic => write!(f, "not an ArtOfVM program (bad magic number)"),
+            ContainerError::UnsupportedVersion(v) => write!(f, "unsupported format version {v}, expected {FORMAT_VERSION}"),
+            ContainerError::Truncated => write!(f, "file is truncated"),
+            ContainerError::MissingSection(name) => write!(f, "missing {name} section"),
+            ContainerError::Malformed(msg) => write!(f, "malformed program: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/*
+    .aovm layout, every integer is little endian
+
+    magic           4 bytes, "AOVM"
+    version         u16
+    entry           u32
+    section count   u32
+    section table   (kind u8, offset u32, len u32) for every section, offsets are from the start of the file
+    sections        code is raw bytes, the data, symbol & debug sections are bincode
+*/
+impl Program {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let sections = [
+            (SECTION_CODE, self.code.clone()),
+            (SECTION_DATA, serialize(&self.data).unwrap()),
+            (SECTION_SYMBOLS, serialize(&self.symbols).unwrap()),
+            (SECTION_DEBUG, serialize(&self.debug).unwrap()),
+        ];
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.entry as u32).to_le_bytes());
+        bytes.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+
+        let mut offset = bytes.len() + sections.len()*SECTION_ENTRY_SIZE;
+        for (kind, section) in sections.iter() {
+            bytes.push(*kind);
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            offset += section.len();
+        }
+
+        for (_, section) in sections {
+            bytes.extend(section);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ContainerError> {
+        if bytes.get(..MAGIC.len()) != Some(&MAGIC) {
+            return Err(ContainerError::BadMagic);
+        }
+
+        let mut r = Reader { bytes, i: MAGIC.len() };
+
+        let version = u16::from_le_bytes(r.bytes()?);
+        if version != FORMAT_VERSION {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+
+        let entry = r.u32()? as Address;
+        let count = r.u32()? as usize;
+
+        let (mut code, mut data, mut symbols, mut debug) = (None, None, None, None);
+
+        for _ in 0..count {
+            let [kind] = r.bytes()?;
+            let offset = r.u32()? as usize;
+            let len = r.u32()? as usize;
+
+            let section = bytes.get(offset..offset.checked_add(len).ok_or(ContainerError::Truncated)?)
+                .ok_or(ContainerError::Truncated)?;
+
+            match kind {
+                SECTION_CODE => code = Some(section.to_vec()),
+                SECTION_DATA => data = Some(deserialize(section).map_err(|e| ContainerError::Malformed(format!("data section: {e}")))?),
+                SECTION_SYMBOLS => symbols = Some(deserialize(section).map_err(|e| ContainerError::Malformed(format!("symbol section: {e}")))?),
+                SECTION_DEBUG => debug = Some(deserialize(section).map_err(|e| ContainerError::Malformed(format!("debug section: {e}")))?),
+                _ => {},
+            }
+        }
+
+        let code: Vec<u8> = code.ok_or(ContainerError::MissingSection("code"))?;
+        if entry > code.len() {
+            return Err(ContainerError::Malformed(format!("entry point {entry} is past the end of the code")));
+        }
+
+        Ok(Self {
+            code,
+            data: data.unwrap_or_default(),
+            entry,
+            symbols: symbols.unwrap_or_default(),
+            debug: debug.unwrap_or_default(),
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    i: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], ContainerError> {
+        let bytes = self.bytes.get(self.i..self.i+N).ok_or(ContainerError::Truncated)?;
+        self.i += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, ContainerError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+}
@@ -0,0 +1,85 @@
+use {
+    crate::{
+        disassembler::{format_immed, format_instr},
+        vm::{Address, Immediate, Instruction, Register},
+    },
+    std::{collections::BTreeMap, io::Write, time::Duration},
+};
+
+// what VirtualMachine::set_tracer's tracer is given after every instruction it executes
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub instr_ptr: Address,              // start of the executed instruction
+    pub instr: Instruction,
+    pub next: Address,                   // where execution continues, e.g. a jump's target if it was taken
+    pub stack_depth: usize,              // after the instruction was executed
+    pub modified: Vec<(Register, Immediate)>, // registers whose value changed & their new value
+    pub interrupt_time: Option<Duration>, // time spent in the handler, if the instruction was an INT
+}
+
+// any FnMut(&TraceEvent) works as a tracer
+pub trait Tracer {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+impl<F: FnMut(&TraceEvent)> Tracer for F {
+    fn trace(&mut self, event: &TraceEvent) {
+        self(event)
+    }
+}
+
+/*
+    writes a line per instruction, e.g.
+
+         0  @ R0 u8$1                     stack 0  R0 = u8$1
+         4  $$ R0                         stack 1
+*/
+pub struct TextTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let regs: Vec<String> = event.modified.iter().map(|(reg, immed)| format!("  R{reg} = {}", format_immed(*immed))).collect();
+        let asm = format_instr(event.instr, &BTreeMap::new());
+
+        // a trace that can't be written (e.g. a closed pipe) shouldn't stop the program
+        let _ = writeln!(self.out, "{:>6}  {asm:<28}  stack {}{}", event.instr_ptr, event.stack_depth, regs.concat());
+    }
+}
+
+/*
+    writes a JSON object per instruction, one per line, e.g.
+
+    {"ip":0,"instr":"@ R0 u8$1","stack":0,"regs":{"R0":"u8$1"}}
+
+    immediates are written as they would be in ArtOfASM, so that their types are kept
+*/
+pub struct JsonTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        let regs: Vec<String> = event.modified.iter().map(|(reg, immed)| format!("\"R{reg}\":\"{}\"", format_immed(*immed))).collect();
+        let asm = format_instr(event.instr, &BTreeMap::new());
+
+        let _ = writeln!(
+            self.out,
+            "{{\"ip\":{},\"instr\":\"{asm}\",\"stack\":{},\"regs\":{{{}}}}}",
+            event.instr_ptr, event.stack_depth, regs.join(","),
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use {
+    crate::{
+        assembler::Opcode, vm::{decode, Address, Immediate, Instruction, Register, REGISTER_COUNT},
+    },
+    std::fmt,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    UnknownOpcode,
+    Truncated,                           // the instruction's operands run past the end of the code
+    InvalidRegister(Register),
+    InvalidImmediateTag(u8),
+    JumpOutOfBounds(Address),            // jumps (or calls) past the end of the code
+    JumpIntoInstruction(Address),        // jumps (or calls) into the middle of another instruction
+    EntryOutOfBounds(Address),
+    EntryIntoInstruction(Address),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub instr_ptr: Address,              // start of the offending instruction
+    pub opcode: u8,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyErrorKind::UnknownOpcode => write!(f, "unknown opcode"),
+            VerifyErrorKind::Truncated => write!(f, "instruction is cut off by the end of the program"),
+            VerifyErrorKind::InvalidRegister(reg) => write!(f, "register R{reg} doesn't exist, only R0 to R{} do", REGISTER_COUNT-1),
+            VerifyErrorKind::InvalidImmediateTag(tag) => write!(f, "unknown immediate type tag {tag}"),
+            VerifyErrorKind::JumpOutOfBounds(at) => write!(f, "jump target {at} is past the end of the program"),
+            VerifyErrorKind::JumpIntoInstruction(at) => write!(f, "jump target {at} is in the middle of an instruction"),
+            VerifyErrorKind::EntryOutOfBounds(at) => write!(f, "entry point {at} is past the end of the program"),
+            VerifyErrorKind::EntryIntoInstruction(at) => write!(f, "entry point {at} is in the middle of an instruction"),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid instruction at {} (opcode {}): {}", self.instr_ptr, self.opcode, self.kind)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/*
+    checks that code can be run without the VM misreading it, i.e. that
+    every opcode is known & every instruction's operands fit in the code
+    every register is R0 to R15 & every immediate has a known type tag
+    every jump & call target, as well as the entry point, is the start of an instruction (or the end of the code, which halts)
+
+    once an instruction can't be decoded, where the ones after it start is unknown, so checking stops there
+*/
+pub fn verify(code: &[u8], entry: Address) -> Result<(), Vec<VerifyError>> {
+    let mut errs = vec![];
+    let mut starts = vec![false; code.len()+1];
+    let mut targets = vec![];
+    let mut at = 0;
+
+    while at < code.len() {
+        let opcode = code[at];
+        let err = |kind| VerifyError { instr_ptr: at, opcode, kind };
+
+        if opcode > Opcode::RET as u8 {
+            errs.push(err(VerifyErrorKind::UnknownOpcode));
+            break;
+        }
+
+        let (instr, len) = match decode(code, at) {
+            Some(decoded) => decoded,
+            _ => {
+                errs.push(err(VerifyErrorKind::Truncated));
+                break;
+            },
+        };
+
+        starts[at] = true;
+
+        for reg in instr.registers().into_iter().filter(|reg| *reg >= REGISTER_COUNT) {
+            errs.push(err(VerifyErrorKind::InvalidRegister(reg)));
+        }
+
+        // unknown tags are decoded as Immediate::None, which there's no way to write in an instr
+        if let Some(Immediate::None()) = immediate(instr) {
+            let tag_at = match instr {
+                Instruction::PUSH(_) => at+1,
+                _ => at+2,
+            };
+
+            errs.push(err(VerifyErrorKind::InvalidImmediateTag(code[tag_at])));
+        }
+
+        if let Some(target) = instr.target() {
+            targets.push((at, opcode, target));
+        }
+
+        at += len;
+    }
+
+    starts[code.len()] = true;
+
+    for (instr_ptr, opcode, target) in targets {
+        let kind = match starts.get(target) {
+            None => VerifyErrorKind::JumpOutOfBounds(target),
+            // targets past where checking stopped can't be told apart from ones into the middle of an instr
+            Some(false) if target < at => VerifyErrorKind::JumpIntoInstruction(target),
+            _ => continue,
+        };
+
+        errs.push(VerifyError { instr_ptr, opcode, kind });
+    }
+
+    // reported at the entry point itself, as there's no instr that goes there
+    let kind = match starts.get(entry) {
+        None => Some(VerifyErrorKind::EntryOutOfBounds(entry)),
+        Some(false) if entry < at => Some(VerifyErrorKind::EntryIntoInstruction(entry)),
+        _ => None,
+    };
+
+    if let Some(kind) = kind {
+        errs.push(VerifyError { instr_ptr: entry, opcode: code.get(entry).copied().unwrap_or_default(), kind });
+    }
+
+    match errs.is_empty() {
+        true => Ok(()),
+        _ => Err(errs),
+    }
+}
+
+fn immediate(instr: Instruction) -> Option<Immediate> {
+    match instr {
+        Instruction::PUSH(immed)
+        | Instruction::LDI(_, immed)
+        | Instruction::SHR(_, immed)
+        | Instruction::SHL(_, immed) => Some(immed),
+        _ => None,
+    }
+}
@@ -1,9 +1,25 @@
-use std::{char, fs::{read_to_string, self}, mem};
+use {
+    crate::{interrupt::{self, as_addr, InterruptContext, InterruptHandler}, program::Program, trace::{TraceEvent, Tracer}},
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, fmt, io::{self, Read, Write}, mem, ops::Range, time::{Duration, Instant}},
+};
 
 pub type Register = usize;
 pub type Address = usize;
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+// checking the clock is much slower than executing an instruction, so the deadline is only checked this often
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+// stands in for a decoded instr index when there's no instr, e.g. past the end of the code
+const NO_INSTR: usize = usize::MAX;
+const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+// cells HEAP_ALLOC may grow the heap to, 256 MiB worth of immediates
+const DEFAULT_HEAP_LIMIT: usize = 1 << 24;
+
+pub const REGISTER_COUNT: Register = 16;
+
+pub const ADDR_SIZE: usize = mem::size_of::<u32>();
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum Immediate {
     None(),
     U8(u8),
@@ -19,7 +35,7 @@ pub enum Immediate {
 }
 
 // (see art_of_vm::assembler::Opcode for an opcode reference)
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Instruction {
     NOP(),                               // do nothing
     HLT(),                               // end program execution
@@ -48,593 +64,602 @@ pub enum Instruction {
     HSTORER(Register),                   // store immediate from stack to heap at address from register
     HLOAD(Address),                      // load immediate from heap and push to stack
     HLOADR(Register),                    // load immediate from heap at address from register and push to stack
+    CALL(Address),                       // push return address to call stack & jmp to location
+    CALLR(Register),                     // push return address to call stack & jmp to location from register
+    RET(),                               // pop return address from call stack & jmp to it
+}
+
+impl Instruction {
+    // where the instruction jumps (or calls) to, None for instrs that don't or only do so through a register
+    pub fn target(self) -> Option<Address> {
+        match self {
+            Instruction::JMP(at)
+            | Instruction::JE(at)
+            | Instruction::JNE(at)
+            | Instruction::JG(at)
+            | Instruction::JL(at)
+            | Instruction::CALL(at) => Some(at),
+            _ => None,
+        }
+    }
+
+    // every register the instruction reads or writes
+    pub fn registers(self) -> Vec<Register> {
+        match self {
+            Instruction::PUSHR(reg)
+            | Instruction::POP(reg)
+            | Instruction::LDI(reg, _)
+            | Instruction::SHR(reg, _)
+            | Instruction::SHL(reg, _)
+            | Instruction::HSTORER(reg)
+            | Instruction::HLOADR(reg)
+            | Instruction::CALLR(reg) => vec![reg],
+            Instruction::CPY(a, b)
+            | Instruction::CMP(a, b)
+            | Instruction::ADD(a, b)
+            | Instruction::SUB(a, b)
+            | Instruction::MUL(a, b)
+            | Instruction::DIV(a, b)
+            | Instruction::AND(a, b)
+            | Instruction::OR(a, b)
+            | Instruction::XOR(a, b) => vec![a, b],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitStatus {
+    Halted,                              // HLT was executed or the end of the program was reached
+    Paused,                              // run_for/run_until stopped early, execution can be resumed
+    OutOfFuel,                           // the fuel budget ran out, add more fuel to resume
+    DeadlineExceeded,                    // the deadline passed, set a later one to resume
+    Watchpoint,                          // the last instr wrote to a watched location, see take_watch_hits
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watch {
+    Heap(Range<Address>),                // the heap cells start..end
+    Register(Register),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Location {
+    Heap(Address),
+    Register(Register),
+}
+
+// a write to a watched location by HSTORE, HSTORER, POP, LDI or CPY
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WatchHit {
+    pub instr_ptr: Address,              // start of the writing instruction
+    pub location: Location,
+    pub old: Immediate,
+    pub new: Immediate,
+}
+
+impl Watch {
+    pub fn contains(&self, location: Location) -> bool {
+        match (self, location) {
+            (Watch::Heap(range), Location::Heap(addr)) => range.contains(&addr),
+            (Watch::Register(a), Location::Register(b)) => *a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Step {
+    Executed(Address, Instruction),      // executed the instruction that started at the address
+    Halted,                              // nothing was executed as the program has already halted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmErrorKind {
+    StackUnderflow,                      // popped a value off of an empty stack
+    TypeMismatch(&'static str),          // operands of the named operation are of different/unsupported types
+    InvalidAddress(Immediate),           // a non-integer immediate was used as a heap address
+    HeapOutOfBounds(Address),            // accessed a heap cell past the end of the heap
+    InvalidChar(Immediate),              // a string in the heap holds something that isn't a unicode U32
+    DivideByZero,
+    ShiftOverflow,                       // shifted by a negative amount or by more bits than the type has
+    UnknownInterrupt(Address),
+    Interrupt(String),                   // raised by a host interrupt handler
+    Io(String),                          // reading stdin or writing stdout/stderr failed
+    CallStackOverflow,                   // CALL went deeper than the max call depth
+    CallStackUnderflow,                  // RET without a matching CALL
+    Panic(String),                       // raised by the PANIC interrupt with the program's message
+    TruncatedInstruction,                // the instruction's operands run past the end of the program
+    InvalidRegister(Register),           // the instruction names a register past R15
+    HeapExhausted(usize),                // allocating this many cells would grow the heap past its limit
+    ZeroSizedAlloc,                      // HEAP_ALLOC was asked for 0 cells
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub instr_ptr: Address,              // start of the faulting instruction
+    pub opcode: u8,
+    pub kind: VmErrorKind,
+}
+
+impl fmt::Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmErrorKind::StackUnderflow => write!(f, "attempted to pop off value from stack when no values are on the stack"),
+            VmErrorKind::TypeMismatch(op) => write!(f, "can only {op} if the operands store the same type of value"),
+            VmErrorKind::InvalidAddress(i) => write!(f, "valid addresses are u8, u16, u32, & u64, got {i:?}"),
+            VmErrorKind::HeapOutOfBounds(addr) => write!(f, "heap address {addr} is out of bounds"),
+            VmErrorKind::InvalidChar(i) => write!(f, "expected U32 unicode char within string, got {i:?}"),
+            VmErrorKind::DivideByZero => write!(f, "attempted to divide by zero"),
+            VmErrorKind::ShiftOverflow => write!(f, "attempted to shift by a negative amount or by more bits than the value has"),
+            VmErrorKind::UnknownInterrupt(i) => write!(f, "unknown interrupt '{i}'"),
+            VmErrorKind::Interrupt(msg) => write!(f, "interrupt failed: {msg}"),
+            VmErrorKind::Io(msg) => write!(f, "i/o error: {msg}"),
+            VmErrorKind::CallStackOverflow => write!(f, "exceeded the max call depth"),
+            VmErrorKind::CallStackUnderflow => write!(f, "attempted to return when no calls are on the call stack"),
+            VmErrorKind::Panic(msg) => write!(f, "panicked with err message:\n{msg}"),
+            VmErrorKind::TruncatedInstruction => write!(f, "instruction is cut off by the end of the program"),
+            VmErrorKind::InvalidRegister(reg) => write!(f, "register R{reg} doesn't exist, only R0 to R{} do", REGISTER_COUNT-1),
+            VmErrorKind::HeapExhausted(n) => write!(f, "can't allocate {n} heap cells without growing the heap past its limit"),
+            VmErrorKind::ZeroSizedAlloc => write!(f, "can't allocate 0 heap cells"),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fault at {} (opcode {}): {}", self.instr_ptr, self.opcode, self.kind)
+    }
+}
+
+impl std::error::Error for VmError {}
+
+// why a decoded instruction can't be executed
+#[derive(Debug, Copy, Clone)]
+enum Invalid {
+    Truncated,                           // cut off by the end of the code
+    Register(Register),                  // names a register past R15
+}
+
+// an instruction decoded ahead of time, so that running it doesn't have to decode it again
+#[derive(Debug, Copy, Clone)]
+struct Decoded {
+    addr: Address,
+    len: usize,
+    instr: Result<Instruction, Invalid>,
+    next: usize,                         // index of the instr after it
+    target: usize,                       // index of the instr it jumps (or calls) to
 }
 
 pub struct VirtualMachine {
-    instr_ptr: Address,
+    pc: usize,                           // index of the next instr to execute in decoded
+    executing: Address,                  // bit position of the instr being executed
     instr_mem: Vec<u8>,
+    decoded: Vec<Decoded>,
+    index: Vec<usize>,                   // bit position -> index in decoded of the instr starting there
     virt_mem: Vec<Immediate>,
     stack: Vec<Immediate>,
-    reg: [Immediate; 16],
+    call_stack: Vec<Address>,
+    max_call_depth: usize,
+    heap_limit: usize,
+    reg: [Immediate; REGISTER_COUNT],
 
     flag_eq: bool,
     flag_gt: bool,
-    is_exe: bool,
+    halted: bool,
+
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    interrupts: HashMap<Address, Box<dyn InterruptHandler>>,
+    watches: Vec<Watch>,
+    watch_hits: Vec<WatchHit>,
+    tracer: Option<Box<dyn Tracer>>,
+    interrupt_time: Option<Duration>,    // how long the last INT's handler took, only timed when tracing
+
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    stdin: Box<dyn Read>,
 }
 
 impl VirtualMachine {
     pub fn new(instr_mem: Vec<u8>, heap_max: usize) -> Self {
-        Self {
-            instr_ptr: 0,
+        let mut vm = Self {
+            pc: NO_INSTR,
+            executing: 0,
+            decoded: vec![],
+            index: vec![NO_INSTR; instr_mem.len()],
             instr_mem,
             virt_mem: vec![Immediate::None(); heap_max],
             stack: vec![],
-            reg: [Immediate::U8(0); 16],
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            heap_limit: DEFAULT_HEAP_LIMIT,
+            reg: [Immediate::U8(0); REGISTER_COUNT],
 
             flag_eq: false,
             flag_gt: false,
-            is_exe: false,
-        }
+            halted: false,
+
+            fuel: None,
+            deadline: None,
+            interrupts: interrupt::builtins().into_iter().collect(),
+            watches: vec![],
+            watch_hits: vec![],
+            tracer: None,
+            interrupt_time: None,
+
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            stdin: Box::new(io::stdin()),
+        };
+
+        vm.pc = vm.decode_from(0);
+        vm
     }
 
-    pub fn exec(&mut self) {
-        if self.is_exe {
-            return;
-        }
+    // calls the tracer after every executed instruction, None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
 
-        self.is_exe = true;
+    // redirects what the WRITE interrupt (and host interrupts using ctx.stdout) writes to
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        self.stdout = stdout;
+    }
 
-        while self.instr_ptr < self.instr_mem.len() && self.is_exe {
-            let decoded = self.decode();
-            self.execute(decoded);
-            self.instr_ptr += 1;
-        }
+    // redirects what the PANIC interrupt (and host interrupts using ctx.stderr) writes to
+    pub fn set_stderr(&mut self, stderr: Box<dyn Write>) {
+        self.stderr = stderr;
     }
 
-    fn decode_immed(&mut self) -> Immediate {
-        self.instr_ptr += 2;
+    // redirects what the READ_LINE interrupt (and host interrupts using ctx.stdin) reads from
+    pub fn set_stdin(&mut self, stdin: Box<dyn Read>) {
+        self.stdin = stdin;
+    }
 
-        match self.instr_mem[self.instr_ptr-1] {
-            0 => Immediate::U8(self.instr_mem[self.instr_ptr] as u8),
-            1 => Immediate::I8(self.instr_mem[self.instr_ptr] as i8),
-            2 => {
-                let size = mem::size_of::<u16>();
-                let val = u16::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
+    // loads the program's code & copies its data segment to the start of the heap, growing the heap if it doesn't fit
+    pub fn from_program(program: Program, heap_max: usize) -> Self {
+        let mut vm = Self::new(program.code, heap_max.max(program.data.len()));
+        vm.virt_mem[..program.data.len()].copy_from_slice(&program.data);
+        vm.pc = vm.decode_from(program.entry);
+        vm
+    }
 
-                self.instr_ptr += size-1;
-                Immediate::U16(val)
-            },
-            3 => {
-                let size = mem::size_of::<i16>();
-                let val = i16::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::I16(val)
-            },
-            4 => {
-                let size = mem::size_of::<u32>();
-                let val = u32::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::U32(val)
-            },
-            5 => {
-                let size = mem::size_of::<i32>();
-                let val = i32::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::I32(val)
-            },
-            6 => {
-                let size = mem::size_of::<u64>();
-                let val = u64::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::U64(val)
-            },
-            7 => {
-                let size = mem::size_of::<i64>();
-                let val = i64::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::I64(val)
+    // registers a handler for INT [int], replacing the built-in or previously registered one
+    pub fn register_interrupt<H: InterruptHandler + 'static>(&mut self, int: Address, handler: H) {
+        self.interrupts.insert(int, Box::new(handler));
+    }
+
+    pub fn remove_interrupt(&mut self, int: Address) -> Option<Box<dyn InterruptHandler>> {
+        self.interrupts.remove(&int)
+    }
+
+    pub fn exec(&mut self) -> Result<ExitStatus, VmError> {
+        self.run_until(|_| false)
+    }
+
+    // executes exactly one instruction
+    pub fn step(&mut self) -> Result<Step, VmError> {
+        if self.is_halted() {
+            self.halted = true;
+            return Ok(Step::Halted);
+        }
+
+        let pc = self.pc;
+        let decoded = self.decoded[pc];
+        // only copied when tracing, to tell which registers the instr modified
+        let reg = self.tracer.as_ref().map(|_| self.reg);
+
+        let res = match decoded.instr {
+            Ok(instr) => {
+                self.pc = decoded.next;
+                self.executing = decoded.addr;
+                self.execute(instr, decoded.target, decoded.addr+decoded.len).map(|_| instr)
             },
-            8 => {
-                let size = mem::size_of::<f32>();
-                let val = f32::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::F32(val)
+            Err(Invalid::Truncated) => Err(VmErrorKind::TruncatedInstruction),
+            Err(Invalid::Register(reg)) => Err(VmErrorKind::InvalidRegister(reg)),
+        };
+
+        match res {
+            Ok(instr) => {
+                if let Some(reg) = reg {
+                    let event = TraceEvent {
+                        instr_ptr: decoded.addr,
+                        instr,
+                        next: self.instr_ptr(),
+                        stack_depth: self.stack.len(),
+                        modified: (0..reg.len()).filter(|i| reg[*i] != self.reg[*i]).map(|i| (i, self.reg[i])).collect(),
+                        interrupt_time: self.interrupt_time.take(),
+                    };
+
+                    if let Some(tracer) = self.tracer.as_mut() {
+                        tracer.trace(&event);
+                    }
+                }
+
+                Ok(Step::Executed(decoded.addr, instr))
             },
-            9 => {
-                let size = mem::size_of::<f64>();
-                let val = f64::from_le_bytes(
-                    self.instr_mem[self.instr_ptr..][..size].try_into().unwrap()
-                );
-
-                self.instr_ptr += size-1;
-                Immediate::F64(val)
+            Err(kind) => {
+                self.pc = pc;
+                self.halted = true;
+                Err(VmError { instr_ptr: decoded.addr, opcode: self.instr_mem[decoded.addr], kind })
             },
-            _ => Immediate::None(),
         }
     }
 
-    fn decode(&mut self) -> Instruction {
-        match self.instr_mem[self.instr_ptr] {
-            0 => {
-                self.instr_ptr += 1;
-                Instruction::NOP()
-            },
-            1 => {
-                self.instr_ptr += 1;
-                Instruction::HLT()
-            },
-            2 => Instruction::INT({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Address
-            }),
-            3 => Instruction::PUSH(self.decode_immed()),
-            4 => Instruction::PUSHR({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            5 => Instruction::POP({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            6 => Instruction::LDI({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }, self.decode_immed()),
-            7 => Instruction::CPY({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            8 => Instruction::JMP({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            9 => Instruction::JE({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            10 => Instruction::JNE({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            11 => Instruction::JG({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            12 => Instruction::JL({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            13 => Instruction::CMP({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            14 => Instruction::ADD({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            15 => Instruction::SUB({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            16 => Instruction::MUL({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            17 => Instruction::DIV({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            18 => Instruction::AND({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            19 => Instruction::OR({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            20 => Instruction::XOR({
-                self.instr_ptr += 2;
-                self.instr_mem[self.instr_ptr-1] as Register
-            }, self.instr_mem[self.instr_ptr] as Register),
-            21 => Instruction::SHR({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }, self.decode_immed()),
-            22 => Instruction::SHL({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }, self.decode_immed()),
-            23 => Instruction::HSTORE({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Address
-            }),
-            24 => Instruction::HSTORER({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            25 => Instruction::HLOAD({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Address
-            }),
-            26 => Instruction::HLOADR({
-                self.instr_ptr += 1;
-                self.instr_mem[self.instr_ptr] as Register
-            }),
-            _ => Instruction::NOP(),
+    // executes at most n instructions
+    pub fn run_for(&mut self, n: usize) -> Result<ExitStatus, VmError> {
+        self.run(Some(n), |_| false)
+    }
+
+    // executes instructions until the program halts or pred returns true, pred is checked before each instruction
+    pub fn run_until<F: FnMut(&Self) -> bool>(&mut self, pred: F) -> Result<ExitStatus, VmError> {
+        self.run(None, pred)
+    }
+
+    fn run<F: FnMut(&Self) -> bool>(&mut self, mut limit: Option<usize>, mut pred: F) -> Result<ExitStatus, VmError> {
+        let mut executed: u64 = 0;
+
+        loop {
+            if self.is_halted() {
+                return Ok(ExitStatus::Halted);
+            }
+
+            if limit == Some(0) || pred(self) {
+                return Ok(ExitStatus::Paused);
+            }
+
+            if self.fuel == Some(0) {
+                return Ok(ExitStatus::OutOfFuel);
+            }
+
+            if let Some(deadline) = self.deadline {
+                if executed.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                    return Ok(ExitStatus::DeadlineExceeded);
+                }
+            }
+
+            let hits = self.watch_hits.len();
+            self.step()?;
+            executed += 1;
+
+            if let Some(fuel) = self.fuel.as_mut() {
+                *fuel -= 1;
+            }
+
+            if let Some(n) = limit.as_mut() {
+                *n -= 1;
+            }
+
+            if self.watch_hits.len() > hits {
+                return Ok(ExitStatus::Watchpoint);
+            }
         }
     }
 
-    fn execute(&mut self, instr: Instruction) {
-        match instr {
-            Instruction::NOP() => {},
-            Instruction::HLT() => self.is_exe = false,
-            Instruction::INT(i) => match i {
-                /*
-                    WRITE interrupt
-                    params:
-                        start ptr to buf (u8/u16/u32/u64)
-                    desc:
-                        writes buf to stdout
-
-                example printing 'A':
-
-                push 65 ('A' in unicode)
-                str 0
-                push 0
-                int 0
-                */
-                0 => {
-                    let mut buf = String::new();
-                    let mut addr: usize = match self.stack.pop() {
-                        Some(i) => match i {
-                            Immediate::U8(i) => i as usize,
-                            Immediate::U16(i) => i as usize,
-                            Immediate::U32(i) => i as usize,
-                            Immediate::U64(i) => i as usize,
-                            _ => panic!("valid addresses to WRITE interrupt are u8, u16, u32, & u64"),
-                        },
-                        _ => panic!("expected ptr to heap provided to WRITE interrupt"),
-                    };
+    // limits how many instructions exec/run_for/run_until may execute in total, None means unlimited
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
 
-                    while self.virt_mem[addr] != Immediate::U32(0) {
-                        buf.push(match char::from_u32(
-                            match self.virt_mem[addr] {
-                                Immediate::U32(i) => i,
-                                _ => panic!("expected U32 as unicode char provided within message string for WRITE interrupt"),
-                            }
-                        ) {
-                            Some(c) => c,
-                            _ => panic!("invalid char provided within message string for WRITE interrupt"),
-                        });
-                        addr += 1;
-                    }
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(f) = self.fuel.as_mut() {
+            *f = f.saturating_add(fuel);
+        }
+    }
 
-                    print!("{buf}");
-                },
-                /* 
-                    HEAP_ALLOC interrupt
-                    params:
-                        requested alloc size (in immediates, as u8/u16/u32/u64)
-                    desc:
-                        zeroes out first available heap region & returns ptr to it,
-                        if no available heap regions were found, expands the heap with 0s and returns ptr to it
-                
-                example allocating string 'A':
-
-                push 0 ('\0' or null terminator in unicode)
-                push 65 ('A' in unicode)
-
-                push 2
-                int 1
-
-                pop R1
-                ldi R3 1
-                add R1 R3
-                pop R2
-
-                strR R1
-                strR R2
-                */
-                1 => {
-                    let to_alloc: usize = match self.stack.pop() {
-                        Some(i) => match i {
-                            Immediate::U8(i) => i as usize,
-                            Immediate::U16(i) => i as usize,
-                            Immediate::U32(i) => i as usize,
-                            Immediate::U64(i) => i as usize,
-                            _ => panic!("valid alloc size types to HEAP_ALLOC interrupt are u8, u16, u32, & u64"),
-                        },
-                        _ => panic!("expected alloc size provided to HEAP_ALLOC interrupt"),
-                    };
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
 
-                    let mut curr_free = 0;
-                    let mut ptr: Option<usize> = None;
-
-                    for (i, cell) in self.virt_mem.iter().enumerate() {
-                        if let Immediate::None() = cell {
-                            curr_free += 1;
-                            if curr_free >= to_alloc {
-                                ptr = Some(((i as isize - to_alloc as isize) + 1) as usize);
-                                break;
-                            }
-                        } else {
-                            curr_free = 0;
-                        }
-                    }
+    // stops exec/run_for/run_until once the deadline has passed, None means no deadline
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
 
-                    if let None = ptr {
-                        let addr = self.virt_mem.len().clone()-1;
+    pub fn is_halted(&self) -> bool {
+        self.halted || self.pc == NO_INSTR
+    }
 
-                        for _ in 0..to_alloc {
-                            self.virt_mem.push(Immediate::U8(0));
-                        }
+    // bit position of the next instr to execute, the length of the code once the end has been reached
+    pub fn instr_ptr(&self) -> Address {
+        self.decoded.get(self.pc).map_or(self.instr_mem.len(), |d| d.addr)
+    }
 
-                        self.stack.push(Immediate::U64(addr as u64));
-                        return;
-                    }
+    // the next instr to execute, None once halted or if it can't be executed
+    pub fn next_instr(&self) -> Option<Instruction> {
+        match self.is_halted() {
+            true => None,
+            _ => self.decoded[self.pc].instr.ok(),
+        }
+    }
 
-                    let addr = ptr.unwrap();
+    pub fn instr_mem(&self) -> &[u8] {
+        &self.instr_mem
+    }
 
-                    for i in addr..addr+to_alloc {
-                        self.virt_mem[i] = Immediate::U8(0);
-                    }
+    pub fn registers(&self) -> &[Immediate; REGISTER_COUNT] {
+        &self.reg
+    }
 
-                    self.stack.push(Immediate::U64(addr.clone() as u64));
-                },
-                /* 
-                    READ_FILE interrupt
-                    params:
-                        start ptr to file path (u8/u16/u32/u64)
-                    desc:
-                        pushes ptr to buffer in heap then a 1 if successful (1 would be at the top of the stack)
-                        pushes two 0s if unsucessful (err happened)
-                
-                example reading 'A.txt':
-
-                push 0 ('\0' or null terminator in unicode)
-                push 116 ('t' in unicode)
-                push 120 ('x' in unicode)
-                push 116 ('t' in unicode)
-                push 46 ('.' in unicode)
-                push 65 ('A' in unicode)
-
-                str 0
-                str 1
-                str 2
-                str 3
-                str 4
-                str 5
-
-                push 0
-                int 2
-                */
-                2 => {
-                    let mut path = String::new();
-                    let mut addr: usize = match self.stack.pop() {
-                        Some(i) => match i {
-                            Immediate::U8(i) => i as usize,
-                            Immediate::U16(i) => i as usize,
-                            Immediate::U32(i) => i as usize,
-                            Immediate::U64(i) => i as usize,
-                            _ => panic!("valid addresses to READ_FILE interrupt are u8, u16, u32, & u64"),
-                        },
-                        _ => panic!("expected ptr to heap provided to READ_FILE interrupt"),
-                    };
+    pub fn stack(&self) -> &[Immediate] {
+        &self.stack
+    }
 
-                    while self.virt_mem[addr] != Immediate::U32(0) {
-                        path.push(match char::from_u32(
-                            match self.virt_mem[addr] {
-                                Immediate::U32(i) => i,
-                                _ => panic!("expected U32 as unicode char provided within file path string for READ_FILE interrupt"),
-                            }
-                        ) {
-                            Some(c) => c,
-                            _ => panic!("invalid char provided within file path string for READ_FILE interrupt"),
-                        });
-                        addr += 1;
-                    }
+    pub fn call_stack(&self) -> &[Address] {
+        &self.call_stack
+    }
+
+    // writes to the watched location are recorded & make exec/run_for/run_until pause with ExitStatus::Watchpoint
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
 
-                    match read_to_string(path) {
-                        Ok(s) => {
-                            let len = s.chars().count();
-                            self.stack.push(Immediate::U64(len as u64));
+    pub fn remove_watch(&mut self, watch: &Watch) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|w| w != watch);
+        self.watches.len() != len
+    }
 
-                            self.execute(Instruction::INT(1));
-                            let buf_start = match self.stack.pop().unwrap() { Immediate::U64(addr) => addr as usize, _ => unreachable!() };
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
 
-                            for (i, ch) in s.chars().into_iter().enumerate() {
-                                self.virt_mem[buf_start+i] = Immediate::U32(ch as u32);
-                            }
+    // the writes to watched locations since the last call, oldest first
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        mem::take(&mut self.watch_hits)
+    }
 
-                            self.virt_mem[buf_start+len] = Immediate::U32(0);
-                            self.stack.extend_from_slice(&[Immediate::U64(buf_start as u64), Immediate::U8(1)]);
-                        },
-                        _ => self.stack.extend_from_slice(&[Immediate::U64(0), Immediate::U8(0)]),
-                    }
-                },
-                /* 
-                    WRITE_FILE interrupt
-                    params:
-                        start ptr to buf (u8/u16/u32/u64) (first arg)
-                        start ptr to file path (u8/u16/u32/u64)
-                    desc:
-                        attempts to write to file or create file if nonexistant with buf, pushes 0 if err, 1 if success
-                
-                example writing 'A' to 'A.txt':
-
-                push 0 ('\0' or null terminator in unicode)
-                push 116 ('t' in unicode)
-                push 120 ('x' in unicode)
-                push 116 ('t' in unicode)
-                push 46 ('.' in unicode)
-                push 65 ('A' in unicode)
-                str 0
-                str 1
-                str 2
-                str 3
-                str 4
-                str 5
-
-                push 0 ('\0' or null terminator in unicode)
-                push 65 ('A' in unicode)
-                str 6
-                str 7
-
-                push 0
-                push 6
-                int 3
-                */
-                3 => {
-                    let mut buf = String::new();
-                    let mut buf_addr: usize = match self.stack.pop() {
-                        Some(i) => match i {
-                            Immediate::U8(i) => i as usize,
-                            Immediate::U16(i) => i as usize,
-                            Immediate::U32(i) => i as usize,
-                            Immediate::U64(i) => i as usize,
-                            _ => panic!("valid addresses to WRITE_FILE interrupt are u8, u16, u32, & u64"),
-                        },
-                        _ => panic!("expected ptr to heap provided to WRITE_FILE interrupt"),
-                    };
+    // CALLs nested deeper than this raise VmErrorKind::CallStackOverflow
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
 
-                    while self.virt_mem[buf_addr] != Immediate::U32(0) {
-                        buf.push(match char::from_u32(
-                            match self.virt_mem[buf_addr] {
-                                Immediate::U32(i) => i,
-                                _ => panic!("expected U32 as unicode char provided within buffer string for WRITE_FILE interrupt"),
-                            }
-                        ) {
-                            Some(c) => c,
-                            _ => panic!("invalid char provided within buffer string for WRITE_FILE interrupt"),
-                        });
-                        buf_addr += 1;
-                    }
+    // HEAP_ALLOC (& host interrupts using ctx.heap_alloc) raise VmErrorKind::HeapExhausted instead of growing the heap past this
+    pub fn set_heap_limit(&mut self, cells: usize) {
+        self.heap_limit = cells;
+    }
 
-                    let mut path = String::new();
-                    let mut path_addr: usize = match self.stack.pop() {
-                        Some(i) => match i {
-                            Immediate::U8(i) => i as usize,
-                            Immediate::U16(i) => i as usize,
-                            Immediate::U32(i) => i as usize,
-                            Immediate::U64(i) => i as usize,
-                            _ => panic!("valid addresses to WRITE_FILE interrupt are u8, u16, u32, & u64"),
-                        },
-                        _ => panic!("expected ptr to heap provided to WRITE_FILE interrupt"),
-                    };
+    pub fn heap(&self) -> &[Immediate] {
+        &self.virt_mem
+    }
 
-                    while self.virt_mem[path_addr] != Immediate::U32(0) {
-                        path.push(match char::from_u32(
-                            match self.virt_mem[path_addr] {
-                                Immediate::U32(i) => i,
-                                _ => panic!("expected U32 as unicode char provided within path string for WRITE_FILE interrupt"),
-                            }
-                        ) {
-                            Some(c) => c,
-                            _ => panic!("invalid char provided within path string for WRITE_FILE interrupt"),
-                        });
-                        path_addr += 1;
-                    }
+    pub fn flag_eq(&self) -> bool {
+        self.flag_eq
+    }
 
-                    match fs::write(path, buf) {
-                        Ok(_) => self.stack.push(Immediate::U8(1)),
-                        _ => self.stack.push(Immediate::U8(0)),
-                    }
-                },
-                /* 
-                    PANIC interrupt
-                    params:
-                        start ptr to panic message (u64)
-                    desc:
-                        prints out panic message to stderr then exits with error code 1
-                
-                    example panicking with 'A':
-
-                    push 0 ('\0' or null terminator in unicode)
-                    push 65 ('A' in unicode)
-                    
-                    str 0
-                    str 1
-
-                    push 0
-                    int 4
-                */
-                4 => {
-                    let mut buf = String::new();
-                    let mut addr: usize = match self.stack.pop() {
-                        Some(i) => match i {
-                            Immediate::U8(i) => i as usize,
-                            Immediate::U16(i) => i as usize,
-                            Immediate::U32(i) => i as usize,
-                            Immediate::U64(i) => i as usize,
-                            _ => panic!("valid addresses to PANIC interrupt are u8, u16, u32, & u64"),
-                        },
-                        _ => panic!("expected ptr to heap provided to PANIC interrupt"),
-                    };
+    pub fn flag_gt(&self) -> bool {
+        self.flag_gt
+    }
 
-                    while self.virt_mem[addr] != Immediate::U32(0) {
-                        buf.push(match char::from_u32(
-                            match self.virt_mem[addr] {
-                                Immediate::U32(i) => i,
-                                _ => panic!("expected U32 as unicode char provided within message string for PANIC interrupt"),
-                            }
-                        ) {
-                            Some(c) => c,
-                            _ => panic!("invalid char provided within message string for PANIC interrupt"),
-                        });
-                        addr += 1;
-                    }
+    fn pop(&mut self) -> Result<Immediate, VmErrorKind> {
+        self.stack.pop().ok_or(VmErrorKind::StackUnderflow)
+    }
 
-                    eprintln!("panicked with err message:\n{buf}");
-                    std::process::exit(1);
+    fn heap_get(&self, addr: Address) -> Result<Immediate, VmErrorKind> {
+        self.virt_mem.get(addr).copied().ok_or(VmErrorKind::HeapOutOfBounds(addr))
+    }
+
+    fn heap_set(&mut self, addr: Address, immed: Immediate) -> Result<(), VmErrorKind> {
+        let old = self.heap_get(addr)?;
+        self.watch(Location::Heap(addr), old, immed);
+        self.virt_mem[addr] = immed;
+        Ok(())
+    }
+
+    fn reg_set(&mut self, reg: Register, immed: Immediate) {
+        self.watch(Location::Register(reg), self.reg[reg], immed);
+        self.reg[reg] = immed;
+    }
+
+    fn watch(&mut self, location: Location, old: Immediate, new: Immediate) {
+        if self.watches.iter().any(|w| w.contains(location)) {
+            self.watch_hits.push(WatchHit { instr_ptr: self.executing, location, old, new });
+        }
+    }
+
+    /*
+        decodes the instr at addr & every instr that can be reached from it without a CALLR or RET (that isn't decoded already),
+        returning its index in decoded
+
+        jump targets are resolved to indices as they're decoded, so that jumping is as cheap as moving on to the next instr
+    */
+    fn decode_from(&mut self, addr: Address) -> usize {
+        let start = self.decoded.len();
+        let first = self.decode_chain(addr);
+
+        let mut i = start;
+        while i < self.decoded.len() {
+            if let Some(target) = self.decoded[i].instr.ok().and_then(Instruction::target) {
+                self.decoded[i].target = self.decode_chain(target);
+            }
+
+            i += 1;
+        }
+
+        first
+    }
+
+    // decodes instrs one after another from addr until reaching one that's decoded already or the end of the code
+    fn decode_chain(&mut self, mut addr: Address) -> usize {
+        let first = self.index.get(addr).copied().unwrap_or(NO_INSTR);
+        if addr >= self.instr_mem.len() || first != NO_INSTR {
+            return first;
+        }
+
+        let first = self.decoded.len();
+
+        while addr < self.instr_mem.len() && self.index[addr] == NO_INSTR {
+            let i = self.decoded.len();
+            // registers are checked once here, so that executing an instr can index them without checking
+            let (instr, len) = match decode(&self.instr_mem, addr) {
+                Some((instr, len)) => match instr.registers().into_iter().find(|reg| *reg >= REGISTER_COUNT) {
+                    Some(reg) => (Err(Invalid::Register(reg)), len),
+                    _ => (Ok(instr), len),
                 },
-                _ => panic!("unknown interrupt '{i}'"),
+                _ => (Err(Invalid::Truncated), self.instr_mem.len()-addr),
+            };
+
+            self.index[addr] = i;
+            self.decoded.push(Decoded { addr, len, instr, next: NO_INSTR, target: NO_INSTR });
+
+            addr += len;
+            self.decoded[i].next = match self.index.get(addr) {
+                Some(&next) if next != NO_INSTR => next,
+                Some(_) => i+1,
+                _ => NO_INSTR,
+            };
+        }
+
+        first
+    }
+
+    // target is the decoded index of a jump's target & ret_addr the bit position of the instr after it
+    fn execute(&mut self, instr: Instruction, target: usize, ret_addr: Address) -> Result<(), VmErrorKind> {
+        match instr {
+            Instruction::NOP() => {},
+            Instruction::HLT() => self.halted = true,
+            Instruction::INT(i) => {
+                let start = self.tracer.as_ref().map(|_| Instant::now());
+                let handler = match self.interrupts.get_mut(&i) {
+                    Some(h) => h,
+                    _ => return Err(VmErrorKind::UnknownInterrupt(i)),
+                };
+
+                handler.handle(&mut InterruptContext {
+                    stack: &mut self.stack,
+                    reg: &mut self.reg,
+                    heap: &mut self.virt_mem,
+                    heap_limit: self.heap_limit,
+                    stdout: &mut *self.stdout,
+                    stderr: &mut *self.stderr,
+                    stdin: &mut *self.stdin,
+                })?;
+
+                self.interrupt_time = start.map(|start| start.elapsed());
             },
             Instruction::PUSH(immed) => {
                 self.stack.push(immed)
             },
             Instruction::PUSHR(reg) => self.stack.push(self.reg[reg]),
-            Instruction::POP(reg) => match self.stack.pop() {
-                Some(immed) => self.reg[reg] = immed,
-                _ => panic!("attempted to pop off value from stack when no values are on the stack"),
+            Instruction::POP(reg) => {
+                let immed = self.pop()?;
+                self.reg_set(reg, immed);
             },
-            Instruction::LDI(reg, immed) => self.reg[reg] = immed,
-            Instruction::CPY(reg_a, reg_b) => self.reg[reg_b] = self.reg[reg_a].clone(),
-            Instruction::JMP(addr) => self.instr_ptr = addr-1,
-            Instruction::JE(addr) => match self.flag_eq {
-                true => self.execute(Instruction::JMP(addr)),
-                _ => {},
+            Instruction::LDI(reg, immed) => self.reg_set(reg, immed),
+            Instruction::CPY(reg_a, reg_b) => self.reg_set(reg_b, self.reg[reg_a]),
+            Instruction::JMP(_) => self.pc = target,
+            Instruction::JE(_) => if self.flag_eq {
+                self.pc = target;
             },
-            Instruction::JNE(addr) => match self.flag_eq {
-                false => self.execute(Instruction::JMP(addr)),
-                _ => {},
+            Instruction::JNE(_) => if !self.flag_eq {
+                self.pc = target;
             },
-            Instruction::JG(addr) => match self.flag_gt {
-                true => self.execute(Instruction::JMP(addr)),
-                _ => {},
+            Instruction::JG(_) => if self.flag_gt {
+                self.pc = target;
             },
-            Instruction::JL(addr) => match self.flag_gt {
-                false => self.execute(Instruction::JMP(addr)),
-                _ => {},
+            Instruction::JL(_) => if !self.flag_gt {
+                self.pc = target;
             },
             Instruction::CMP(reg_a, reg_b) => {
                 let r1 = self.reg[reg_a];
@@ -642,136 +667,273 @@ impl VirtualMachine {
                 self.flag_eq = r1 == r2;
                 self.flag_gt = r1 > r2;
             },
-            Instruction::ADD(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a+b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a+b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a+b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a+b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a+b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a+b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a+b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a+b)),
-                (Immediate::F32(a), Immediate::F32(b)) => self.stack.push(Immediate::F32(a+b)),
-                (Immediate::F64(a), Immediate::F64(b)) => self.stack.push(Immediate::F64(a+b)),
-                _ => panic!("can only add two registers if they store the same type of value"),
+            Instruction::ADD(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a.wrapping_add(b)),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a.wrapping_add(b)),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a.wrapping_add(b)),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a.wrapping_add(b)),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a.wrapping_add(b)),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a.wrapping_add(b)),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a.wrapping_add(b)),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a.wrapping_add(b)),
+                    (Immediate::F32(a), Immediate::F32(b)) => Immediate::F32(a+b),
+                    (Immediate::F64(a), Immediate::F64(b)) => Immediate::F64(a+b),
+                    _ => return Err(VmErrorKind::TypeMismatch("add two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::SUB(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a-b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a-b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a-b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a-b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a-b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a-b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a-b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a-b)),
-                (Immediate::F32(a), Immediate::F32(b)) => self.stack.push(Immediate::F32(a-b)),
-                (Immediate::F64(a), Immediate::F64(b)) => self.stack.push(Immediate::F64(a-b)),
-                _ => panic!("can only sub two registers if they store the same type of value"),
+            Instruction::SUB(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a.wrapping_sub(b)),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a.wrapping_sub(b)),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a.wrapping_sub(b)),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a.wrapping_sub(b)),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a.wrapping_sub(b)),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a.wrapping_sub(b)),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a.wrapping_sub(b)),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a.wrapping_sub(b)),
+                    (Immediate::F32(a), Immediate::F32(b)) => Immediate::F32(a-b),
+                    (Immediate::F64(a), Immediate::F64(b)) => Immediate::F64(a-b),
+                    _ => return Err(VmErrorKind::TypeMismatch("sub two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::MUL(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a*b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a*b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a*b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a*b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a-b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a*b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a*b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a*b)),
-                (Immediate::F32(a), Immediate::F32(b)) => self.stack.push(Immediate::F32(a*b)),
-                (Immediate::F64(a), Immediate::F64(b)) => self.stack.push(Immediate::F64(a*b)),
-                _ => panic!("can only mul two registers if they store the same type of value"),
+            Instruction::MUL(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a.wrapping_mul(b)),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a.wrapping_mul(b)),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a.wrapping_mul(b)),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a.wrapping_mul(b)),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a.wrapping_mul(b)),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a.wrapping_mul(b)),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a.wrapping_mul(b)),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a.wrapping_mul(b)),
+                    (Immediate::F32(a), Immediate::F32(b)) => Immediate::F32(a*b),
+                    (Immediate::F64(a), Immediate::F64(b)) => Immediate::F64(a*b),
+                    _ => return Err(VmErrorKind::TypeMismatch("mul two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::DIV(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a/b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a/b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a/b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a/b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a/b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a/b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a/b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a/b)),
-                (Immediate::F32(a), Immediate::F32(b)) => self.stack.push(Immediate::F32(a/b)),
-                (Immediate::F64(a), Immediate::F64(b)) => self.stack.push(Immediate::F64(a/b)),
-                _ => panic!("can only div two registers if they store the same type of value"),
+            Instruction::DIV(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(if b == 0 { return Err(VmErrorKind::DivideByZero) } else { a.wrapping_div(b) }),
+                    (Immediate::F32(a), Immediate::F32(b)) => Immediate::F32(a/b),
+                    (Immediate::F64(a), Immediate::F64(b)) => Immediate::F64(a/b),
+                    _ => return Err(VmErrorKind::TypeMismatch("div two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::AND(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a&b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a&b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a&b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a&b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a&b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a&b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a&b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a&b)),
-                _ => panic!("can only bitwise and two registers if they store the same type of value"),
+            Instruction::AND(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a&b),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a&b),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a&b),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a&b),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a&b),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a&b),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a&b),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a&b),
+                    _ => return Err(VmErrorKind::TypeMismatch("bitwise and two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::OR(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a|b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a|b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a|b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a|b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a|b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a|b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a|b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a|b)),
-    
-                _ => panic!("can only bitwise or two registers if they store the same type of value"),
+            Instruction::OR(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a|b),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a|b),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a|b),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a|b),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a|b),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a|b),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a|b),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a|b),
+                    _ => return Err(VmErrorKind::TypeMismatch("bitwise or two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::XOR(reg_a, reg_b) => match (self.reg[reg_a], self.reg[reg_b]) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a^b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a^b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a^b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a^b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a^b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a^b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a^b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a^b)),
-                _ => panic!("can only bitwise and two registers if they store the same type of value"),
+            Instruction::XOR(reg_a, reg_b) => {
+                let res = match (self.reg[reg_a], self.reg[reg_b]) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a^b),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a^b),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a^b),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a^b),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a^b),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a^b),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a^b),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a^b),
+                    _ => return Err(VmErrorKind::TypeMismatch("bitwise xor two registers")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::SHR(reg, immed) => match (self.reg[reg], immed) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a>>b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a>>b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a>>b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a>>b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a>>b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a>>b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a>>b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a>>b)),
-                _ => panic!("can only right shift if reg and immed are of the same type of value"),
+            Instruction::SHR(reg, immed) => {
+                let res = match (self.reg[reg], immed) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a.checked_shr(shift_amount(b)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a.checked_shr(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    _ => return Err(VmErrorKind::TypeMismatch("right shift a register by an immediate")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::SHL(reg, immed) => match (self.reg[reg], immed) {
-                (Immediate::I8(a), Immediate::I8(b)) => self.stack.push(Immediate::I8(a<<b)),
-                (Immediate::I16(a), Immediate::I16(b)) => self.stack.push(Immediate::I16(a<<b)),
-                (Immediate::I32(a), Immediate::I32(b)) => self.stack.push(Immediate::I32(a<<b)),
-                (Immediate::I64(a), Immediate::I64(b)) => self.stack.push(Immediate::I64(a<<b)),
-                (Immediate::U8(a), Immediate::U8(b)) => self.stack.push(Immediate::U8(a<<b)),
-                (Immediate::U16(a), Immediate::U16(b)) => self.stack.push(Immediate::U16(a<<b)),
-                (Immediate::U32(a), Immediate::U32(b)) => self.stack.push(Immediate::U32(a<<b)),
-                (Immediate::U64(a), Immediate::U64(b)) => self.stack.push(Immediate::U64(a<<b)),
-                _ => panic!("can only left shift if reg and immed are of the same type of value"),
+            Instruction::SHL(reg, immed) => {
+                let res = match (self.reg[reg], immed) {
+                    (Immediate::I8(a), Immediate::I8(b)) => Immediate::I8(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::I16(a), Immediate::I16(b)) => Immediate::I16(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::I32(a), Immediate::I32(b)) => Immediate::I32(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::I64(a), Immediate::I64(b)) => Immediate::I64(a.checked_shl(shift_amount(b)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U8(a), Immediate::U8(b)) => Immediate::U8(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U16(a), Immediate::U16(b)) => Immediate::U16(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U32(a), Immediate::U32(b)) => Immediate::U32(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    (Immediate::U64(a), Immediate::U64(b)) => Immediate::U64(a.checked_shl(shift_amount(b as i64)?).ok_or(VmErrorKind::ShiftOverflow)?),
+                    _ => return Err(VmErrorKind::TypeMismatch("left shift a register by an immediate")),
+                };
+
+                self.stack.push(res);
             },
-            Instruction::HSTORE(addr) => self.virt_mem[addr] = match self.stack.pop() {
-                Some(i) => i,
-                _ => panic!("expected value on stack for HSTORE instruction"),
+            Instruction::HSTORE(addr) => {
+                let immed = self.pop()?;
+                self.heap_set(addr, immed)?;
             },
-            Instruction::HSTORER(reg) => self.virt_mem[match self.reg[reg] {
-                Immediate::U8(i) => i as usize,
-                Immediate::U16(i) => i as usize,
-                Immediate::U32(i) => i as usize,
-                Immediate::U64(i) => i as usize,
-                _ => panic!("valid addresses to HSTORER are u8, u16, u32, & u64"),
-            }] = match self.stack.pop() {
-                Some(i) => i,
-                _ => panic!("expected value on stack for HSTORER instruction"),
+            Instruction::HSTORER(reg) => {
+                let addr = as_addr(self.reg[reg])?;
+                let immed = self.pop()?;
+                self.heap_set(addr, immed)?;
+            },
+            Instruction::HLOAD(addr) => self.stack.push(self.heap_get(addr)?),
+            Instruction::HLOADR(reg) => self.stack.push(self.heap_get(as_addr(self.reg[reg])?)?),
+            Instruction::CALL(_) => {
+                if self.call_stack.len() >= self.max_call_depth {
+                    return Err(VmErrorKind::CallStackOverflow);
+                }
+
+                self.call_stack.push(ret_addr);
+                self.pc = target;
+            },
+            // only known once the register is read, so the target might not have been decoded yet
+            Instruction::CALLR(reg) => {
+                let addr = as_addr(self.reg[reg])?;
+                let target = self.decode_from(addr);
+                return self.execute(Instruction::CALL(addr), target, ret_addr);
+            },
+            Instruction::RET() => self.pc = match self.call_stack.pop() {
+                Some(addr) => self.decode_from(addr),
+                _ => return Err(VmErrorKind::CallStackUnderflow),
             },
-            Instruction::HLOAD(addr) => self.stack.push(self.virt_mem[addr]),
-            Instruction::HLOADR(reg) => self.stack.push(self.virt_mem[match self.reg[reg] {
-                Immediate::U8(i) => i as usize,
-                Immediate::U16(i) => i as usize,
-                Immediate::U32(i) => i as usize,
-                Immediate::U64(i) => i as usize,
-                _ => panic!("valid addresses to HLOADR are u8, u16, u32, & u64"),
-            }]),
         }
+
+        Ok(())
+    }
+}
+
+// decodes the instruction starting at code[at] & returns it along with its length in bytes,
+// None if its operands are cut off by the end of the code
+pub fn decode(code: &[u8], at: Address) -> Option<(Instruction, usize)> {
+    let mut r = Reader { code, i: at+1 };
+
+    let instr = match *code.get(at)? {
+        0 => Instruction::NOP(),
+        1 => Instruction::HLT(),
+        2 => Instruction::INT(r.addr()?),
+        3 => Instruction::PUSH(r.immed()?),
+        4 => Instruction::PUSHR(r.reg()?),
+        5 => Instruction::POP(r.reg()?),
+        6 => Instruction::LDI(r.reg()?, r.immed()?),
+        7 => Instruction::CPY(r.reg()?, r.reg()?),
+        8 => Instruction::JMP(r.addr()?),
+        9 => Instruction::JE(r.addr()?),
+        10 => Instruction::JNE(r.addr()?),
+        11 => Instruction::JG(r.addr()?),
+        12 => Instruction::JL(r.addr()?),
+        13 => Instruction::CMP(r.reg()?, r.reg()?),
+        14 => Instruction::ADD(r.reg()?, r.reg()?),
+        15 => Instruction::SUB(r.reg()?, r.reg()?),
+        16 => Instruction::MUL(r.reg()?, r.reg()?),
+        17 => Instruction::DIV(r.reg()?, r.reg()?),
+        18 => Instruction::AND(r.reg()?, r.reg()?),
+        19 => Instruction::OR(r.reg()?, r.reg()?),
+        20 => Instruction::XOR(r.reg()?, r.reg()?),
+        21 => Instruction::SHR(r.reg()?, r.immed()?),
+        22 => Instruction::SHL(r.reg()?, r.immed()?),
+        23 => Instruction::HSTORE(r.addr()?),
+        24 => Instruction::HSTORER(r.reg()?),
+        25 => Instruction::HLOAD(r.addr()?),
+        26 => Instruction::HLOADR(r.reg()?),
+        27 => Instruction::CALL(r.addr()?),
+        28 => Instruction::CALLR(r.reg()?),
+        29 => Instruction::RET(),
+        _ => Instruction::NOP(),
+    };
+
+    Some((instr, r.i-at))
+}
+
+struct Reader<'a> {
+    code: &'a [u8],
+    i: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.code.get(self.i..self.i+N)?.try_into().ok()?;
+        self.i += N;
+        Some(bytes)
     }
-}
\ No newline at end of file
+
+    fn reg(&mut self) -> Option<Register> {
+        let [reg] = self.bytes()?;
+        Some(reg as Register)
+    }
+
+    // addresses (jump targets, heap addresses & interrupt numbers) are little endian u32s
+    fn addr(&mut self) -> Option<Address> {
+        Some(u32::from_le_bytes(self.bytes::<ADDR_SIZE>()?) as Address)
+    }
+
+    // a type tag followed by the little endian value
+    fn immed(&mut self) -> Option<Immediate> {
+        let [tag] = self.bytes()?;
+
+        Some(match tag {
+            0 => Immediate::U8(u8::from_le_bytes(self.bytes()?)),
+            1 => Immediate::I8(i8::from_le_bytes(self.bytes()?)),
+            2 => Immediate::U16(u16::from_le_bytes(self.bytes()?)),
+            3 => Immediate::I16(i16::from_le_bytes(self.bytes()?)),
+            4 => Immediate::U32(u32::from_le_bytes(self.bytes()?)),
+            5 => Immediate::I32(i32::from_le_bytes(self.bytes()?)),
+            6 => Immediate::U64(u64::from_le_bytes(self.bytes()?)),
+            7 => Immediate::I64(i64::from_le_bytes(self.bytes()?)),
+            8 => Immediate::F32(f32::from_le_bytes(self.bytes()?)),
+            9 => Immediate::F64(f64::from_le_bytes(self.bytes()?)),
+            // unknown tags are skipped along with a single byte of value
+            _ => {
+                self.bytes::<1>()?;
+                Immediate::None()
+            },
+        })
+    }
+}
+
+// shift amounts are typed like the shifted value, so negative amounts are a fault rather than a huge shift
+fn shift_amount(b: i64) -> Result<u32, VmErrorKind> {
+    u32::try_from(b).map_err(|_| VmErrorKind::ShiftOverflow)
+}
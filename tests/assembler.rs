@@ -0,0 +1,479 @@
+use {
+    art_of_vm::{
+        assembler::{AsmDiagnostic, Assembler, Opcode, Source},
+        vm::{Immediate, VirtualMachine},
+    },
+    std::{fs, path::PathBuf},
+};
+
+fn source(path: &str) -> Source {
+    Source { path: PathBuf::from(path), text: fs::read_to_string(path).unwrap() }
+}
+
+fn assemble(src: &str) -> Vec<u8> {
+    Assembler::new(format!("{src}\0")).assemble().unwrap().code
+}
+
+fn target(code: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(code[at..at+4].try_into().unwrap())
+}
+
+#[test]
+fn forward_and_backward_labels() {
+    let code = assemble("
+        // c
+        .a
+        // b
+        .b
+        // a
+        .c
+        hlt
+    ");
+
+    assert_eq!(code[0], Opcode::JMP as u8);
+    assert_eq!(target(&code, 1), 15);
+    assert_eq!(target(&code, 6), 10);
+    assert_eq!(target(&code, 11), 5);
+    assert_eq!(code[15], Opcode::HLT as u8);
+}
+
+#[test]
+fn label_heavy_loops() {
+    // counts R0 up to 5 in an outer loop & R1 up to 3 in an inner loop, summing the iterations in R2
+    let code = assemble("
+        @ R0 u8$0
+        @ R1 u8$0
+        @ R2 u8$0
+        @ R3 u8$1
+        @ R4 u8$5
+        @ R5 u8$3
+        // outer
+
+        .inner_done
+            + R0 R3
+            % R0
+            @ R1 u8$0
+            = R0 R4
+            /= done
+            // inner
+
+        .outer
+            = R0 R4
+            /= done
+
+        .inner
+            = R1 R5
+            /= inner_done
+            + R1 R3
+            % R1
+            + R2 R3
+            % R2
+            // inner
+
+        .done
+            hlt
+    ");
+
+    let mut vm = VirtualMachine::new(code, 16);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.registers()[0], Immediate::U8(5));
+    assert_eq!(vm.registers()[2], Immediate::U8(15));
+}
+
+#[test]
+fn call_and_return() {
+    let code = assemble("
+        @ R0 u8$0
+        @ R1 u8$1
+        call inc
+        call twice
+        hlt
+
+        .twice
+            call inc
+            call inc
+            ret
+
+        .inc
+            + R0 R1
+            % R0
+            ret
+    ");
+
+    let mut vm = VirtualMachine::new(code, 16);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.registers()[0], Immediate::U8(3));
+    assert!(vm.call_stack().is_empty());
+}
+
+#[test]
+fn reports_every_error_with_its_location() {
+    let diags = Assembler::new("$ u8$1\n+ R1\nfoo R1\n@ R16 u8$0\n// nowhere\n$ u8$300\n".to_string())
+        .assemble()
+        .unwrap_err();
+
+    let locs: Vec<(usize, usize)> = diags.iter().map(|d| (d.line, d.col)).collect();
+    assert_eq!(locs, vec![(2, 5), (3, 1), (4, 3), (6, 3), (5, 4)]);
+
+    assert_eq!(diags[0], AsmDiagnostic {
+        file: String::new(),
+        line: 2,
+        col: 5,
+        snippet: "+ R1".to_string(),
+        msg: "expected two registers after ADD instr".to_string(),
+    });
+    assert_eq!(diags[4].msg, "unknown label \"nowhere\"");
+}
+
+#[test]
+fn comments_are_skipped_and_kept() {
+    let src = "; header\n$ u8$1 ; push\n/* multi\nline */ % R0\n";
+    let mut assembler = Assembler::new(src.to_string());
+
+    assert_eq!(assembler.assemble().unwrap().code, assemble("$ u8$1\n% R0"));
+
+    let comments: Vec<(usize, usize, &str)> = assembler.comments().iter()
+        .map(|c| (c.line, c.col, c.text.as_str()))
+        .collect();
+    assert_eq!(comments, vec![(1, 1, "; header"), (2, 8, "; push"), (3, 1, "/* multi\nline */")]);
+}
+
+#[test]
+fn data_directives() {
+    let program = Assembler::new("
+        ld nums
+        $ u64$msg
+        hlt
+
+        .msg .string \"hi, \\\"you\\\"\\n\"
+        .nums
+        .u32 7, 8,9
+        .i8 -128
+    ".to_string()).assemble().unwrap();
+
+    let chars: Vec<Immediate> = "hi, \"you\"\n\0".chars().map(|c| Immediate::U32(c as u32)).collect();
+    assert_eq!(program.data[..chars.len()], chars);
+    assert_eq!(program.data[chars.len()..], [Immediate::U32(7), Immediate::U32(8), Immediate::U32(9), Immediate::I8(-128)]);
+
+    let mut vm = VirtualMachine::from_program(program, 4);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.stack(), [Immediate::U32(7), Immediate::U64(0)]);
+}
+
+#[test]
+fn char_hex_binary_and_separated_literals() {
+    let program = Assembler::new("
+        $ u32$'A'
+        $ u32$0x41
+        $ u8$0b1010_0001
+        $ u32$1_000
+        $ u8$' '
+        $ u8$'\\n'
+        $ i16$-0x10
+        ld 0b1
+        .u16 'z', 0xFF_FF
+    ".to_string()).assemble().unwrap();
+
+    assert_eq!(program.data, [Immediate::U16('z' as u16), Immediate::U16(0xFFFF)]);
+    assert_eq!(program.code[program.code.len() - 6..program.code.len() - 1], [25, 1, 0, 0, 0]);
+
+    let mut vm = VirtualMachine::from_program(program, 4);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.stack(), [
+        Immediate::U32(65),
+        Immediate::U32(65),
+        Immediate::U8(0b1010_0001),
+        Immediate::U32(1000),
+        Immediate::U8(b' '),
+        Immediate::U8(b'\n'),
+        Immediate::I16(-16),
+        Immediate::U16(0xFFFF),
+    ]);
+}
+
+#[test]
+fn out_of_range_literals_are_diagnostics() {
+    let diags = Assembler::new("$ u8$0x100\n$ i8$'é'\nld 0x1_0000_0000\n.u8 256\n$ u8$0xZZ\n".to_string())
+        .assemble()
+        .unwrap_err();
+
+    let msgs: Vec<&str> = diags.iter().map(|d| d.msg.as_str()).collect();
+    assert_eq!(msgs, vec![
+        "expected immediate after PUSH instr, 0x100 is out of range for u8",
+        "expected immediate after PUSH instr, 'é' is out of range for i8",
+        "address 0x1_0000_0000 is out of range for u32",
+        "expected u8 values after .u8, 256 is out of range for u8",
+        "expected immediate after PUSH instr, \"0xZZ\" isn't a valid integer",
+    ]);
+}
+
+#[test]
+fn macros_expand_with_arguments() {
+    let src = "
+        .macro print addr
+            $ u64$\\addr
+            int 0
+        .endm
+
+        .macro greet
+            print hi
+        .endm
+
+        greet
+        print bye
+
+        .hi .string \"hi\"
+        .bye .string \"bye\"
+    ";
+
+    let program = Assembler::new(src.to_string()).assemble().unwrap();
+    assert_eq!(program.code, assemble("$ u64$0 int 0 $ u64$3 int 0"));
+}
+
+#[test]
+fn macro_labels_are_local_to_each_expansion() {
+    let src = "
+        .macro count_down r
+            @ \\r u8$3
+            @ R15 u8$1
+            @ R14 u8$0
+        .loop
+            - \\r R15
+            % \\r
+            = \\r R14
+            /! loop
+        .endm
+
+        count_down R0
+        count_down R1
+    ";
+
+    let program = Assembler::new(src.to_string()).assemble().unwrap();
+
+    let mut vm = VirtualMachine::from_program(program, 0);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.registers()[0], Immediate::U8(0));
+    assert_eq!(vm.registers()[1], Immediate::U8(0));
+}
+
+#[test]
+fn macros_are_only_invoked_at_the_start_of_a_line() {
+    let src = "
+        .macro done
+            hlt
+        .endm
+
+        call done
+        done
+        .done
+            ret
+    ";
+
+    let program = Assembler::new(src.to_string()).assemble().unwrap();
+    assert_eq!(program.code, assemble("call done hlt .done ret"));
+}
+
+#[test]
+fn macro_labels_cant_be_named_like_registers_or_instrs() {
+    let src = ".macro a\n.R0\n    @ R0 u8$1\n.endm\n.macro b\n.ret\n    ret\n.endm\n";
+    let diags = Assembler::new(src.to_string()).assemble().unwrap_err();
+
+    let msgs: Vec<(usize, &str)> = diags.iter().map(|d| (d.line, d.msg.as_str())).collect();
+    assert_eq!(msgs, vec![
+        (2, "macro label \"R0\" can't share its name with a register or instr"),
+        (6, "macro label \"ret\" can't share its name with a register or instr"),
+    ]);
+}
+
+#[test]
+fn macro_errors_point_at_the_invocation() {
+    let src = ".macro push_reg r\n    $$ \\r\n.endm\n\npush_reg R99\npush_reg\n.macro forever\n    forever\n.endm\nforever\n";
+    let diags = Assembler::new(src.to_string()).assemble().unwrap_err();
+
+    let locs: Vec<(usize, usize)> = diags.iter().map(|d| (d.line, d.col)).collect();
+    assert_eq!(locs, vec![(6, 1), (10, 1), (5, 1)]);
+
+    assert_eq!(diags[0].msg, "macro \"push_reg\" takes 1 argument(s), got 0");
+    assert!(diags[1].msg.starts_with("macro \"forever\" is nested over 64 deep"));
+    assert_eq!(diags[2].msg, "register R99 doesn't exist, only R0 to R15 do, in macro \"push_reg\" at 2:8");
+    assert_eq!(diags[2].snippet, "push_reg R99");
+}
+
+#[test]
+fn constants_and_expressions() {
+    let program = Assembler::new("
+        .equ BUF 16
+        .define SIZE BUF / 4
+        .equ WRITE 0
+
+        ld BUF+4
+        $ u32$SIZE*2
+        $ u32$(end-start)/4
+        $ i8$-SIZE
+        int WRITE
+        // end+ADDR_SIZE-5
+
+        .start
+        .u32 1, 2, 3, 4
+        .end
+        .u64 end-start, LATER
+        .equ LATER SIZE*SIZE
+        .equ ADDR_SIZE 4
+    ".to_string()).assemble().unwrap();
+
+    let expected = assemble("
+        ld 20
+        $ u32$8
+        $ u32$1
+        $ i8$-4
+        int 0
+        // 3
+    ");
+    assert_eq!(program.code, expected);
+    assert_eq!(program.data[4..], [Immediate::U64(4), Immediate::U64(16)]);
+}
+
+#[test]
+fn code_and_data_labels_arent_mixed_up() {
+    let src = ".loop .string \"x\"\n// loop\ncall msg\n.code\nstr code\nld loop\n.msg .u8 1\n";
+    let diags = Assembler::new(src.to_string()).assemble().unwrap_err();
+
+    let msgs: Vec<(usize, &str)> = diags.iter().map(|d| (d.line, d.msg.as_str())).collect();
+    assert_eq!(msgs, vec![
+        (2, "\"loop\" is a data label, it can't be jumped to or called"),
+        (3, "\"msg\" is a data label, it can't be jumped to or called"),
+        (5, "\"code\" is a code label, it can't be used as a heap address"),
+    ]);
+}
+
+#[test]
+fn constant_errors() {
+    let diags = Assembler::new(".equ A 1/0\n.equ A 2\n$ u8$B*100\n.equ B 3\n.equ C C+1\nld C\n.equ D (1\n".to_string())
+        .assemble()
+        .unwrap_err();
+
+    let msgs: Vec<(usize, &str)> = diags.iter().map(|d| (d.line, d.msg.as_str())).collect();
+    assert_eq!(msgs, vec![
+        (1, "division by zero in \"1/0\""),
+        (2, "\"A\" is already defined"),
+        (6, "expected a addr after HLOAD instr, constant \"C\" is defined in terms of itself"),
+        (7, "expected ) in \"(1\""),
+        (3, "B*100 (300) is out of range for u8"),
+    ]);
+}
+
+#[test]
+fn long_constant_chains_are_evaluated_once_per_constant() {
+    // each constant uses the one before it twice, which would take 2^100 evaluations without caching them
+    let chain: String = (1..=100).map(|i| format!(".equ A{i} A{}+A{}-A{}\n", i-1, i-1, i-1)).collect();
+    let program = Assembler::new(format!(".equ A0 7\n{chain}ld A100\n")).assemble().unwrap();
+
+    assert_eq!(program.code, assemble("ld 7"));
+}
+
+#[test]
+fn spaced_out_expressions_and_non_identifier_labels_are_diagnostics() {
+    let src = ".equ B 4\nstr B + 4\nld B +4\n$ u8$B* 2\n.u8 B - 1\n.a-b\n.1st\nstr B - R0 R1\n";
+    let diags = Assembler::new(src.to_string()).assemble().unwrap_err();
+
+    let msgs: Vec<(usize, &str)> = diags.iter().map(|d| (d.line, d.msg.as_str())).collect();
+    assert_eq!(msgs, vec![
+        (2, "expressions can't contain spaces, did you mean B+4?"),
+        (3, "expressions can't contain spaces, did you mean B+4?"),
+        (4, "expressions can't contain spaces, did you mean u8$B*2?"),
+        (5, "expressions can't contain spaces, did you mean B-1?"),
+        (6, "label names have to be identifiers, got \"a-b\""),
+        (7, "label names have to be identifiers, got \"1st\""),
+    ]);
+}
+
+#[test]
+fn includes_are_searched_next_to_the_file_then_in_include_dirs() {
+    let program = Assembler::from_sources(vec![source("tests/include/main.artofasm")], &[PathBuf::from("tests/include/lib")])
+        .assemble()
+        .unwrap();
+
+    let mut vm = VirtualMachine::from_program(program, 0);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.registers()[0], Immediate::U8(2));
+
+    let diags = Assembler::from_sources(vec![source("tests/include/main.artofasm")], &[]).assemble().unwrap_err();
+    assert_eq!(diags[0].file, "tests/include/main.artofasm");
+    assert_eq!((diags[0].line, diags[0].col), (8, 10));
+    assert_eq!(diags[0].msg, "unable to find included file \"helpers.artofasm\"");
+}
+
+#[test]
+fn include_cycles_are_diagnostics() {
+    let diags = Assembler::from_sources(vec![source("tests/include/cycle_a.artofasm")], &[]).assemble().unwrap_err();
+
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].file, "tests/include/cycle_b.artofasm");
+    assert_eq!(diags[0].line, 2);
+    assert_eq!(diags[0].msg, "include cycle: tests/include/cycle_a.artofasm -> tests/include/cycle_b.artofasm -> tests/include/cycle_a.artofasm");
+}
+
+#[test]
+fn sources_share_labels_and_name_their_file_in_diagnostics() {
+    let sources = vec![
+        Source { path: PathBuf::from("main.artofasm"), text: "call twice\nhlt\n".to_string() },
+        Source { path: PathBuf::from("twice.artofasm"), text: ".twice\n$ u8$1\n$ u8$1\nret\n".to_string() },
+    ];
+
+    let program = Assembler::from_sources(sources, &[]).assemble().unwrap();
+    assert_eq!(program.code, assemble("call twice hlt .twice $ u8$1 $ u8$1 ret"));
+
+    let sources = vec![
+        Source { path: PathBuf::from("main.artofasm"), text: ".twice\n// twice\n".to_string() },
+        Source { path: PathBuf::from("twice.artofasm"), text: "\n.twice\n%\n".to_string() },
+    ];
+
+    let diags = Assembler::from_sources(sources, &[]).assemble().unwrap_err();
+    let locs: Vec<(&str, usize, usize)> = diags.iter().map(|d| (d.file.as_str(), d.line, d.col)).collect();
+    assert_eq!(locs, vec![("twice.artofasm", 2, 1), ("twice.artofasm", 3, 2)]);
+    assert_eq!(diags[0].to_string(), "twice.artofasm:2:1: label \"twice\" is already defined\n    .twice\n    ^");
+}
+
+#[test]
+fn debug_lines_point_at_the_source_of_every_instr() {
+    let sources = vec![
+        Source { path: PathBuf::from("main.artofasm"), text: ".macro two\n    $ u8$2\n.endm\n\n_\n    two\n".to_string() },
+        Source { path: PathBuf::from("end.artofasm"), text: "hlt\n".to_string() },
+    ];
+
+    let program = Assembler::from_sources(sources, &[]).assemble().unwrap();
+    let lines: Vec<(usize, usize, usize, usize)> = program.debug.lines.iter().map(|l| (l.addr, l.file, l.line, l.col)).collect();
+
+    // instrs from a macro are at its invocation
+    assert_eq!(program.debug.files, vec!["main.artofasm", "end.artofasm"]);
+    assert_eq!(lines, vec![(0, 0, 5, 1), (1, 0, 6, 5), (4, 1, 1, 1)]);
+    assert_eq!(program.debug.location(1).as_deref(), Some("main.artofasm:6"));
+    assert_eq!(program.debug.location(2), None);
+
+    let program = Assembler::from_sources(vec![source("tests/include/main.artofasm")], &[PathBuf::from("tests/include/lib")])
+        .assemble()
+        .unwrap();
+
+    let inc = program.symbols.iter().find(|sym| sym.name == "inc").unwrap().addr;
+    assert_eq!(program.debug.location(inc).as_deref(), Some("tests/include/lib/helpers.artofasm:2"));
+    assert_eq!(program.debug.location(0).as_deref(), Some("tests/include/lib/prologue.artofasm:3"));
+}
+
+#[test]
+fn entry_points_must_start_an_instr() {
+    let msgs = |src: &str| -> Vec<String> {
+        Assembler::new(src.to_string()).assemble().unwrap_err().into_iter().map(|d| d.msg).collect()
+    };
+
+    assert_eq!(msgs(".entry 2\n$ u16$51204\n"), ["entry point 2 is in the middle of an instruction"]);
+    assert_eq!(msgs(".entry 5\n$ u16$51204\n"), ["entry point 5 is past the end of the code"]);
+
+    // the end of the code is the appended HLT
+    assert_eq!(Assembler::new(".entry 4\n$ u16$51204\n".to_string()).assemble().unwrap().entry, 4);
+}
@@ -0,0 +1,17 @@
+use {
+    art_of_vm::{trace::TraceEvent, vm::VirtualMachine},
+    std::{cell::RefCell, rc::Rc},
+};
+
+// runs the VM to completion with a tracer handing every event to record, along with what it has recorded so far
+pub fn traced<T: 'static>(mut vm: VirtualMachine, recorded: T, record: impl Fn(&mut T, &TraceEvent) + 'static) -> T {
+    let recorded = Rc::new(RefCell::new(recorded));
+    let recorder = recorded.clone();
+
+    vm.set_tracer(Some(Box::new(move |event: &TraceEvent| record(&mut recorder.borrow_mut(), event))));
+    vm.exec().unwrap();
+
+    // the tracer holds onto its Rc for as long as the VM is around
+    drop(vm);
+    Rc::into_inner(recorded).unwrap().into_inner()
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use {
+    art_of_vm::{
+        assembler::{Assembler, Source},
+        coverage::{Branch, Coverage},
+        program::Program,
+        vm::VirtualMachine,
+    },
+    common::traced,
+    std::path::PathBuf,
+};
+
+fn run(program: &Program) -> Coverage {
+    traced(VirtualMachine::from_program(program.clone(), 0), Coverage::new(program), Coverage::record)
+}
+
+// counts R0 up to 3, then skips over .never
+const COUNT: &str = "\
+@ R0 u8$0
+@ R1 u8$3
+@ R2 u8$1
+.loop
+    + R0 R2
+    % R0
+    = R0 R1
+    /! loop
+= R0 R2
+/= never
+hlt
+.never
+    $ u8$1
+";
+
+fn assemble(path: &str, src: &str) -> Program {
+    let source = Source { path: PathBuf::from(path), text: src.to_string() };
+    Assembler::from_sources(vec![source], &[]).assemble().unwrap()
+}
+
+fn count() -> Program {
+    assemble("count.artofasm", COUNT)
+}
+
+#[test]
+fn branches_are_counted_both_ways() {
+    let program = count();
+    let coverage = run(&program);
+
+    let jne = program.debug.lines.iter().find(|l| l.line == 8).unwrap().addr;
+    let je = program.debug.lines.iter().find(|l| l.line == 10).unwrap().addr;
+
+    assert_eq!(coverage.hits(jne), 3);
+    assert_eq!(coverage.branch(jne), Branch { taken: 2, not_taken: 1 });
+    assert_eq!(coverage.branch(je), Branch { taken: 0, not_taken: 1 });
+    assert_eq!(coverage.hits(program.symbols.iter().find(|sym| sym.name == "never").unwrap().addr), 0);
+
+    // the HLT the assembler appends isn't counted
+    assert_eq!(coverage.summary(&program), "instructions: 10/11 (90.9%), branches: 3/4 (75.0%)");
+}
+
+#[test]
+fn lcov_reports_are_mapped_to_source_lines() {
+    let program = count();
+
+    assert_eq!(run(&program).lcov(&program), "\
+TN:
+SF:count.artofasm
+DA:1,1
+DA:2,1
+DA:3,1
+DA:5,3
+DA:6,3
+DA:7,3
+DA:8,3
+DA:9,1
+DA:10,1
+DA:11,1
+DA:13,0
+BRDA:8,0,0,2
+BRDA:8,0,1,1
+BRDA:10,0,0,0
+BRDA:10,0,1,1
+BRF:4
+BRH:3
+LF:11
+LH:10
+end_of_record
+");
+
+    // branches on lines that never ran are marked with -
+    let program = assemble("skip.artofasm", "// end\n/= end\n.end\n");
+    let lcov = run(&program).lcov(&program);
+    assert!(lcov.contains("DA:2,0\nBRDA:2,0,0,-\nBRDA:2,0,1,-\nBRF:2\nBRH:0\n"), "{lcov}");
+
+    // there's no file to name in the SF line of a source without a path
+    let program = Assembler::new("// end\n/= end\n.end\n".to_string()).assemble().unwrap();
+    assert_eq!(run(&program).lcov(&program), "");
+
+    let program = Program { debug: Default::default(), ..count() };
+    assert_eq!(run(&program).lcov(&program), "");
+}
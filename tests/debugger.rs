@@ -0,0 +1,132 @@
+use art_of_vm::{
+    assembler::Assembler,
+    debugger::{Command, Debugger},
+    vm::Immediate,
+};
+
+fn debugger(src: &str) -> Debugger {
+    Debugger::new(Assembler::new(src.to_string()).assemble().unwrap(), 16)
+}
+
+const TWICE: &str = "
+@ R0 u8$1
+call twice
+@ R2 u8$9
+hlt
+.twice
+    + R0 R0
+    % R0
+    ret
+";
+
+#[test]
+fn breakpoints_by_label_and_address() {
+    let mut dbg = debugger(TWICE);
+
+    assert_eq!(dbg.command("b twice"), "breakpoint 1 at 14 (.twice)");
+    assert_eq!(dbg.command("break 4"), "breakpoint 2 at 4");
+    assert_eq!(dbg.command("b nowhere"), "no label named \"nowhere\"");
+    assert_eq!(dbg.command("b 100"), "100 is past the end of the program");
+    assert_eq!(dbg.breakpoints(), [14, 4]);
+
+    assert!(dbg.command("c").starts_with("hit breakpoint 2\nline 3\n"));
+    assert_eq!(dbg.vm().instr_ptr(), 4);
+    assert!(dbg.command("c").starts_with("hit breakpoint 1\n"));
+    assert_eq!(dbg.vm().registers()[0], Immediate::U8(1));
+
+    assert_eq!(dbg.command("d 4"), "deleted the breakpoint at 4");
+    assert_eq!(dbg.command("c"), "the program has halted");
+    assert_eq!(dbg.vm().registers()[0], Immediate::U8(2));
+    assert_eq!(dbg.vm().registers()[2], Immediate::U8(9));
+}
+
+#[test]
+fn next_runs_calls_until_they_return() {
+    let mut dbg = debugger(TWICE);
+
+    dbg.command("s");
+    assert_eq!(dbg.command("n"), "line 4\n    0  @ R0 u8$1\n    4  call twice\n=>  9  @ R2 u8$9\n   13  hlt\n   .twice\n   14  + R0 R0");
+    assert_eq!(dbg.vm().registers()[0], Immediate::U8(2));
+
+    // unless a breakpoint is hit inside the call
+    dbg.command("restart");
+    dbg.command("b twice");
+    dbg.command("s");
+    assert!(dbg.command("n").starts_with("hit breakpoint 1\n"));
+    assert_eq!(dbg.vm().call_stack(), [9]);
+}
+
+#[test]
+fn stepping_stops_at_breakpoints_and_watchpoints() {
+    let mut dbg = debugger(TWICE);
+
+    dbg.command("b twice");
+    assert!(dbg.command("step 10").starts_with("hit breakpoint 1
+"));
+    assert_eq!(dbg.vm().instr_ptr(), 14);
+
+    // the breakpoint execution is paused at is stepped off of
+    dbg.command("w R0");
+    assert!(dbg.command("step 10").starts_with("R0 written at 17 (line 8): u8$1 -> u8$2\n"));
+    assert_eq!(dbg.vm().instr_ptr(), 19);
+}
+
+#[test]
+fn printing_state() {
+    let mut dbg = debugger("$ u8$3 $ i16$-2 @ R15 f32$1.5 = R0 R1 str 2 hlt");
+    dbg.command("step 4");
+
+    assert_eq!(dbg.command("reg R15"), "R15 = f32$1.5");
+    assert_eq!(dbg.command("reg 16"), "register R16 doesn't exist, only R0 to R15 do");
+    assert!(dbg.command("reg").starts_with("R0  = u8$0\nR1  = u8$0\n"));
+    assert_eq!(dbg.command("flags"), "flag_eq = true\nflag_gt = false");
+    assert_eq!(dbg.command("stack"), "[0] u8$3\n[1] i16$-2");
+
+    dbg.command("s");
+    assert_eq!(dbg.command("heap 1 0x3"), "[1] none$\n[2] i16$-2");
+    assert_eq!(dbg.command("heap 16"), "the heap only has 16 cells");
+    assert_eq!(dbg.command("heap 18446744073709551615"), "the heap only has 16 cells");
+    assert_eq!(dbg.command("heap"), "heap expects 1 argument(s)");
+}
+
+#[test]
+fn faults_are_reported_by_source_location() {
+    let mut dbg = debugger("_\n% R0\nhlt");
+
+    assert_eq!(Command::parse("c"), Ok(Command::Continue));
+    assert_eq!(dbg.command("c"), "line 2: fault at 1 (opcode 5): attempted to pop off value from stack when no values are on the stack");
+    assert_eq!(dbg.command("s"), "the program has halted");
+}
+
+#[test]
+fn watchpoints_report_the_old_and_new_values() {
+    let mut dbg = debugger("
+        @ R0 u8$1
+        $ u8$5
+        str buf
+        $ u8$6
+        str 3
+        @ R0 u8$2
+        hlt
+    .buf
+        .u8 0
+    ");
+
+    assert_eq!(dbg.command("watch R0"), "watching R0");
+    assert_eq!(dbg.command("w buf"), "watching heap[0]");
+    assert_eq!(dbg.command("watch 2 4"), "watching heap[2..4]");
+    assert_eq!(dbg.command("watch R0"), "R0 is already watched");
+    assert_eq!(dbg.command("watch R0 2"), "registers can't be watched as a range");
+    assert_eq!(dbg.command("watch 4 2"), "the range 4..2 is empty");
+    assert!(dbg.command("watch 18446744073709551615").starts_with("the heap only has "));
+    assert_eq!(dbg.command("watch nowhere"), "no data label named \"nowhere\"");
+
+    assert!(dbg.command("c").starts_with("R0 written at 0 (line 2): u8$0 -> u8$1\n"));
+    assert!(dbg.command("c").starts_with("heap[0] written at 7 (line 4): u8$0 -> u8$5\n"));
+    assert!(dbg.command("c").starts_with("heap[3] written at 15 (line 6): none$ -> u8$6\n"));
+
+    assert_eq!(dbg.command("unwatch R0"), "stopped watching R0");
+    assert_eq!(dbg.command("unwatch R0"), "R0 isn't watched");
+    assert_eq!(dbg.command("watches"), "heap[0]\nheap[2..4]");
+    assert_eq!(dbg.command("c"), "the program has halted");
+}
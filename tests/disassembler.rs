@@ -0,0 +1,62 @@
+use {
+    art_of_vm::{assembler::Assembler, disassembler::disassemble, program::{DebugInfo, Program}},
+    std::fs,
+};
+
+// the reassembled program's debug info points into the disassembly, not the original source
+fn reassemble(program: &Program) -> Program {
+    let reassembled = Assembler::new(disassemble(program)).assemble().unwrap();
+    Program { debug: program.debug.clone(), ..reassembled }
+}
+
+#[test]
+fn golden_programs_round_trip() {
+    for path in ["tests/hello_world.aovm", "tests/fs_test.aovm"] {
+        let program = Program::from_bytes(&fs::read(path).unwrap()).unwrap();
+        let reassembled = reassemble(&program);
+        assert_eq!(reassembled, program, "{path}");
+    }
+}
+
+#[test]
+fn jump_targets_get_labels_and_immediates_keep_their_types() {
+    let program = Assembler::new("
+        @ R0 i8$-5
+        $ f32$1.5
+        $ f64$-0.1
+        .loop
+            = R0 R1
+            /= done
+            /! loop
+            call 12345
+        .done
+            hlt
+        .u8 1, 2
+        .u32 7, 0
+        .string \"tab\\there \\\"quoted\\\"\"
+        .i64 -1
+    ".to_string()).assemble().unwrap();
+
+    // without symbols every jump target gets a synthesized label
+    let disassembled = disassemble(&Program { symbols: vec![], debug: DebugInfo::default(), ..program.clone() });
+
+    let code = &disassembled[disassembled.find("; code\n").unwrap()..];
+    assert_eq!(code, "\
+; code
+/*      0 | 06 00 01 fb                   */ @ R0 i8$-5  ; LDI
+/*      4 | 03 08 00 00 c0 3f             */ $ f32$1.5  ; PUSH
+/*     10 | 03 09 9a 99 99 99 99 99 b9 bf */ $ f64$-0.1  ; PUSH
+.lbl_20
+/*     20 | 0d 00 01                      */ = R0 R1  ; CMP
+/*     23 | 09 26 00 00 00                */ /= lbl_38  ; JE
+/*     28 | 0a 14 00 00 00                */ /! lbl_20  ; JNE
+/*     33 | 1b 39 30 00 00                */ call 12345  ; CALL
+.lbl_38
+/*     38 | 01                            */ hlt  ; HLT
+/*     39 | 01                            */ ; hlt, appended by the assembler
+");
+    assert!(disassembled.contains("/* heap      4 */ .string \"tab\\there \\\"quoted\\\"\"\n"), "{disassembled}");
+
+    assert!(disassemble(&program).contains("*/ /! loop  ; JNE at line 8\n"));
+    assert_eq!(reassemble(&program), program);
+}
@@ -0,0 +1,106 @@
+use {
+    art_of_vm::{
+        assembler::Assembler,
+        interrupt::{InterruptContext, INT_READ_FILE, INT_WRITE_FILE},
+        program::Program,
+        vm::{Immediate, VirtualMachine, VmErrorKind},
+    },
+    std::{cell::RefCell, collections::HashMap, fs, io::{self, Write}, rc::Rc},
+};
+
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Capture {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+fn load(path: &str) -> VirtualMachine {
+    let program = Program::from_bytes(&fs::read(path).unwrap()).unwrap();
+    VirtualMachine::from_program(program, 1024)
+}
+
+#[test]
+fn hello_world() {
+    let mut vm = load("tests/hello_world.aovm");
+    let stdout = Capture::default();
+    vm.set_stdout(Box::new(stdout.clone()));
+
+    vm.exec().unwrap();
+    assert_eq!(stdout.contents(), "Hello, world!\n");
+}
+
+#[test]
+fn fs_test() {
+    let mut vm = load("tests/fs_test.aovm");
+    let stdout = Capture::default();
+    vm.set_stdout(Box::new(stdout.clone()));
+
+    // keep the files in memory so that running the test doesn't touch tests/fs_test.txt
+    let files: Rc<RefCell<HashMap<String, String>>> = Rc::default();
+
+    let written = files.clone();
+    vm.register_interrupt(INT_WRITE_FILE, move |ctx: &mut InterruptContext| {
+        let buf_addr = ctx.pop_addr()?;
+        let buf = ctx.read_str(buf_addr)?;
+        let path_addr = ctx.pop_addr()?;
+        let path = ctx.read_str(path_addr)?;
+
+        written.borrow_mut().insert(path, buf);
+        ctx.push(Immediate::U8(1));
+        Ok(())
+    });
+
+    let read = files.clone();
+    vm.register_interrupt(INT_READ_FILE, move |ctx: &mut InterruptContext| {
+        let addr = ctx.pop_addr()?;
+        let path = ctx.read_str(addr)?;
+        let contents = read.borrow()[&path].clone();
+
+        let buf_start = ctx.alloc_str(&contents)?;
+        ctx.stack.extend_from_slice(&[Immediate::U64(buf_start as u64), Immediate::U8(1)]);
+        Ok(())
+    });
+
+    vm.exec().unwrap();
+    assert_eq!(stdout.contents(), "Hello, world!");
+    assert_eq!(files.borrow()["tests/fs_test.txt"], "Hello, world!");
+}
+
+#[test]
+fn read_line_reads_stdin_a_line_at_a_time() {
+    // echoes 2 lines, then tries to read a 3rd
+    let program = Assembler::new("int 5\n% R1\nint 0\nint 5\n% R1\nint 0\nint 5\n".to_string()).assemble().unwrap();
+    let mut vm = VirtualMachine::from_program(program, 0);
+    let stdout = Capture::default();
+    vm.set_stdout(Box::new(stdout.clone()));
+    vm.set_stdin(Box::new("hi\r\nthere".as_bytes()));
+
+    vm.exec().unwrap();
+    assert_eq!(stdout.contents(), "hithere");
+    assert_eq!(vm.stack(), [Immediate::U64(0), Immediate::U8(0)]);
+}
+
+#[test]
+fn panics_are_written_to_stderr() {
+    let program = Assembler::new(".msg .string \"oops\"\n$ u64$msg\nint 4\n".to_string()).assemble().unwrap();
+    let mut vm = VirtualMachine::from_program(program, 0);
+    let stderr = Capture::default();
+    vm.set_stderr(Box::new(stderr.clone()));
+
+    assert_eq!(vm.exec().unwrap_err().kind, VmErrorKind::Panic("oops".to_string()));
+    assert_eq!(stderr.contents(), "panicked with err message:\noops\n");
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use {
+    art_of_vm::{
+        assembler::Assembler,
+        interrupt::{InterruptContext, INT_WRITE},
+        profiler::Profile,
+        program::Program,
+        vm::VirtualMachine,
+    },
+    common::traced,
+    std::{thread, time::Duration},
+};
+
+fn run(program: &Program) -> Profile {
+    let mut vm = VirtualMachine::from_program(program.clone(), 4);
+    // keep the output of the test programs out of the test's, taking long enough to be timed
+    vm.register_interrupt(INT_WRITE, |_: &mut InterruptContext| {
+        thread::sleep(Duration::from_millis(1));
+        Ok(())
+    });
+
+    traced(vm, Profile::new(program), Profile::record)
+}
+
+fn assemble(src: &str) -> Program {
+    Assembler::new(src.to_string()).assemble().unwrap()
+}
+
+const LOOP: &str = "
+    @ R0 u8$0
+    @ R1 u8$3
+.loop
+    call inc
+    = R0 R1
+    /! loop
+    int 0
+    hlt
+.inc
+    $$ R0
+    $ u8$1
+    % R2
+    + R0 R2
+    % R0
+    % R3
+    ret
+";
+
+#[test]
+fn instrs_are_counted_per_address_opcode_and_label() {
+    let program = assemble(LOOP);
+    let profile = run(&program);
+
+    // 2 LDIs, 3 * (CALL, CMP, JNE), INT, HLT & 3 * 7 instrs in inc
+    assert_eq!(profile.total(), 2 + 9 + 2 + 21);
+    assert_eq!(profile.by_address()[0], (8, 3));
+    assert_eq!(profile.by_address().last(), Some(&(26, 1)));
+
+    assert_eq!(&profile.by_opcode(&program)[..3], [("POP".to_string(), 9), ("ADD".to_string(), 3), ("CALL".to_string(), 3)]);
+    assert_eq!(profile.by_label(&program), [
+        ("inc".to_string(), 21),
+        ("loop".to_string(), 11),
+        ("(no label)".to_string(), 2),
+    ]);
+
+    let ints = profile.interrupts();
+    assert_eq!((ints.len(), ints[0].0, ints[0].1), (1, INT_WRITE, 1));
+    assert!(ints[0].2 >= Duration::from_millis(1), "{:?}", ints[0].2);
+
+    let report = profile.report(&program);
+    assert!(report.starts_with("executed 34 instructions\n\nby opcode\n           9  26.47%  POP\n"), "{report}");
+    assert!(report.contains("           3   8.82%       8  call inc\n"), "{report}");
+    assert!(report.contains("\ninterrupts\n           1 calls"), "{report}");
+}
+
+#[test]
+fn folded_stacks_name_functions_by_label() {
+    let program = assemble(LOOP);
+    assert_eq!(run(&program).folded(&program), "entry 13\nentry;inc 21\n");
+
+    // calls into functions without a label are named by address
+    let program = Program { symbols: vec![], ..program };
+    assert_eq!(run(&program).folded(&program), "entry 13\nentry;lbl_27 21\n");
+}
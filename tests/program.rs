@@ -0,0 +1,73 @@
+use art_of_vm::{
+    assembler::Assembler,
+    program::{ContainerError, Program, Symbol, FORMAT_VERSION, MAGIC},
+    verifier::{verify, VerifyErrorKind},
+    vm::{Immediate, VirtualMachine},
+};
+
+fn program() -> Program {
+    Assembler::new("
+        .entry start
+        .msg .string \"hi\"
+        .skipped
+            @ R0 u8$1
+        .start
+            @ R1 u8$2
+            ld msg
+    ".to_string()).assemble().unwrap()
+}
+
+#[test]
+fn container_round_trips() {
+    let program = program();
+    let bytes = program.to_bytes();
+
+    assert_eq!(bytes[..4], MAGIC);
+    assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), FORMAT_VERSION);
+    assert_eq!(Program::from_bytes(&bytes).unwrap(), program);
+
+    assert_eq!(program.entry, 4);
+    assert_eq!(program.symbols, [
+        Symbol { name: "msg".to_string(), addr: 0, is_data: true },
+        Symbol { name: "skipped".to_string(), addr: 0, is_data: false },
+        Symbol { name: "start".to_string(), addr: 4, is_data: false },
+    ]);
+}
+
+#[test]
+fn execution_starts_at_the_entry_point() {
+    let mut vm = VirtualMachine::from_program(program(), 0);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.registers()[0], Immediate::U8(0));
+    assert_eq!(vm.registers()[1], Immediate::U8(2));
+    assert_eq!(vm.stack(), [Immediate::U32('h' as u32)]);
+}
+
+#[test]
+fn stale_and_foreign_files_are_rejected() {
+    let bytes = program().to_bytes();
+
+    // what programs looked like before the container format
+    assert_eq!(Program::from_bytes(&[16, 0, 0, 0, 0, 0, 0, 0, 3, 0]), Err(ContainerError::BadMagic));
+
+    let mut newer = bytes.clone();
+    newer[4..6].copy_from_slice(&(FORMAT_VERSION+1).to_le_bytes());
+    assert_eq!(Program::from_bytes(&newer), Err(ContainerError::UnsupportedVersion(FORMAT_VERSION+1)));
+
+    assert_eq!(Program::from_bytes(&bytes[..bytes.len()-1]), Err(ContainerError::Truncated));
+    assert_eq!(Program::from_bytes(&bytes[..8]), Err(ContainerError::Truncated));
+}
+
+#[test]
+fn entry_points_in_the_middle_of_an_instr_load_but_dont_verify() {
+    let mut bytes = program().to_bytes();
+    bytes[6..10].copy_from_slice(&1u32.to_le_bytes());
+
+    // so that they can still be disassembled & debugged
+    let program = Program::from_bytes(&bytes).unwrap();
+    assert_eq!(program.entry, 1);
+
+    let errs = verify(&program.code, program.entry).unwrap_err();
+    assert_eq!(errs.iter().map(|e| &e.kind).collect::<Vec<_>>(), [&VerifyErrorKind::EntryIntoInstruction(1)]);
+}
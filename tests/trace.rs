@@ -0,0 +1,76 @@
+mod common;
+
+use {
+    art_of_vm::{
+        assembler::Assembler,
+        interrupt::InterruptContext,
+        trace::{JsonTracer, TextTracer, TraceEvent, Tracer},
+        vm::{Immediate, Instruction, VirtualMachine},
+    },
+    common::traced,
+    std::{thread, time::Duration},
+};
+
+fn events(vm: VirtualMachine) -> Vec<TraceEvent> {
+    traced(vm, vec![], |events: &mut Vec<TraceEvent>, event| events.push(event.clone()))
+}
+
+fn trace(src: &str) -> Vec<TraceEvent> {
+    let program = Assembler::new(src.to_string()).assemble().unwrap();
+    events(VirtualMachine::from_program(program, 0))
+}
+
+#[test]
+fn every_executed_instr_is_traced() {
+    let events = trace("@ R0 u8$1 @ R1 u8$0 = R0 R0 /= skip $ u8$7 .skip : R0 R2 $$ R0 % R3");
+
+    let ips: Vec<usize> = events.iter().map(|e| e.instr_ptr).collect();
+    assert_eq!(ips, [0, 4, 8, 11, 19, 22, 24, 26]);
+
+    // LDI of the value a register already holds doesn't modify it
+    assert_eq!(events[0].modified, [(0, Immediate::U8(1))]);
+    assert_eq!(events[1].modified, []);
+    assert_eq!((events[3].instr, events[3].next), (Instruction::JE(19), 19));
+    assert_eq!(events[4].modified, [(2, Immediate::U8(1))]);
+    assert_eq!(events[5].stack_depth, 1);
+    assert_eq!((events[6].stack_depth, events[6].modified.as_slice()), (0, [(3, Immediate::U8(1))].as_slice()));
+}
+
+#[test]
+fn only_ints_are_timed() {
+    let program = Assembler::new("int 9\n_\n".to_string()).assemble().unwrap();
+    let mut vm = VirtualMachine::from_program(program, 0);
+    vm.register_interrupt(9, |_: &mut InterruptContext| {
+        thread::sleep(Duration::from_millis(2));
+        Ok(())
+    });
+
+    let times: Vec<Option<Duration>> = events(vm).iter().map(|e| e.interrupt_time).collect();
+    assert!(times[0] >= Some(Duration::from_millis(2)), "{times:?}");
+    assert_eq!(times[1..], [None, None]);
+}
+
+#[test]
+fn text_and_json_formats() {
+    let event = TraceEvent {
+        instr_ptr: 4,
+        instr: Instruction::LDI(2, Immediate::I16(-3)),
+        next: 8,
+        stack_depth: 1,
+        modified: vec![(2, Immediate::I16(-3)), (5, Immediate::F32(0.5))],
+        interrupt_time: None,
+    };
+
+    let mut text = vec![];
+    TextTracer::new(&mut text).trace(&event);
+    assert_eq!(String::from_utf8(text).unwrap(), "     4  @ R2 i16$-3                   stack 1  R2 = i16$-3  R5 = f32$0.5\n");
+
+    let mut json = vec![];
+    let mut tracer = JsonTracer::new(&mut json);
+    tracer.trace(&event);
+    tracer.trace(&TraceEvent { instr_ptr: 8, instr: Instruction::RET(), next: 13, stack_depth: 0, modified: vec![], interrupt_time: None });
+    assert_eq!(String::from_utf8(json).unwrap(), "\
+{\"ip\":4,\"instr\":\"@ R2 i16$-3\",\"stack\":1,\"regs\":{\"R2\":\"i16$-3\",\"R5\":\"f32$0.5\"}}
+{\"ip\":8,\"instr\":\"ret\",\"stack\":0,\"regs\":{}}
+");
+}
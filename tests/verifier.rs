@@ -0,0 +1,77 @@
+use {
+    art_of_vm::{
+        assembler::Assembler,
+        program::Program,
+        verifier::{verify, VerifyError, VerifyErrorKind},
+    },
+    std::fs,
+};
+
+fn kinds(code: &[u8]) -> Vec<(usize, VerifyErrorKind)> {
+    verify(code, 0).unwrap_err().into_iter().map(|e| (e.instr_ptr, e.kind)).collect()
+}
+
+#[test]
+fn assembled_programs_pass() {
+    for path in ["tests/hello_world.aovm", "tests/fs_test.aovm"] {
+        let program = Program::from_bytes(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(verify(&program.code, program.entry), Ok(()), "{path}");
+    }
+
+    let program = Assembler::new(".loop $ f64$1.5 @ R15 i8$-1 /= end call loop .end".to_string()).assemble().unwrap();
+    assert_eq!(verify(&program.code, program.entry), Ok(()));
+}
+
+#[test]
+fn bad_registers_and_immediate_tags() {
+    // POP R16, CPY R0 R200, PUSH with tag 10, LDI R1 with tag 255
+    let code = [5, 16, 7, 0, 200, 3, 10, 0, 6, 1, 255, 0, 1];
+
+    assert_eq!(kinds(&code), vec![
+        (0, VerifyErrorKind::InvalidRegister(16)),
+        (2, VerifyErrorKind::InvalidRegister(200)),
+        (5, VerifyErrorKind::InvalidImmediateTag(10)),
+        (8, VerifyErrorKind::InvalidImmediateTag(255)),
+    ]);
+}
+
+#[test]
+fn bad_jump_targets() {
+    // JMP 10 (the CALL), JE 2 (inside of the JMP), CALL 100, HLT
+    let code = [8, 10, 0, 0, 0, 9, 2, 0, 0, 0, 27, 100, 0, 0, 0, 1];
+
+    assert_eq!(kinds(&code), vec![
+        (5, VerifyErrorKind::JumpIntoInstruction(2)),
+        (10, VerifyErrorKind::JumpOutOfBounds(100)),
+    ]);
+}
+
+#[test]
+fn unknown_opcodes_and_truncated_instrs_stop_checking() {
+    assert_eq!(verify(&[0, 30, 5, 99], 0).unwrap_err(), vec![
+        VerifyError { instr_ptr: 1, opcode: 30, kind: VerifyErrorKind::UnknownOpcode },
+    ]);
+
+    // the JMP's target can't be checked as the LDI after it is cut off
+    assert_eq!(kinds(&[8, 7, 0, 0, 0, 6, 0, 4, 1]), vec![(5, VerifyErrorKind::Truncated)]);
+
+    assert_eq!(
+        verify(&[3, 4, 1], 0).unwrap_err()[0].to_string(),
+        "invalid instruction at 0 (opcode 3): instruction is cut off by the end of the program",
+    );
+}
+
+#[test]
+fn bad_entry_points() {
+    // PUSH u16$51204, HLT
+    let code = [3, 2, 4, 200, 1];
+
+    assert_eq!(verify(&code, 4), Ok(()));
+    assert_eq!(verify(&code, 5), Ok(()));
+    assert_eq!(verify(&code, 2).unwrap_err(), vec![
+        VerifyError { instr_ptr: 2, opcode: 4, kind: VerifyErrorKind::EntryIntoInstruction(2) },
+    ]);
+    assert_eq!(verify(&code, 6).unwrap_err(), vec![
+        VerifyError { instr_ptr: 6, opcode: 0, kind: VerifyErrorKind::EntryOutOfBounds(6) },
+    ]);
+}
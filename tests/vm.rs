@@ -0,0 +1,300 @@
+use {
+    art_of_vm::{
+        assembler::Assembler,
+        interrupt::{InterruptContext, INT_HEAP_ALLOC},
+        verifier::verify,
+        vm::{ExitStatus, Immediate, Instruction, Location, Step, VirtualMachine, VmErrorKind, Watch, WatchHit},
+    },
+    std::{io, time::Instant},
+};
+
+fn vm(src: &str) -> VirtualMachine {
+    VirtualMachine::from_program(Assembler::new(src.to_string()).assemble().unwrap(), 0)
+}
+
+// LDI R0 u32$0x01_07_00_03, whose immediate is PUSH u8$7 & HLT when read from its 4th bit on
+const HIDDEN: [u8; 7] = [6, 0, 4, 3, 0, 7, 1];
+
+#[test]
+fn jumps_into_the_middle_of_an_instr_decode_from_there() {
+    let mut code = vec![8, 8, 0, 0, 0];
+    code.extend(HIDDEN);
+    code.push(1);
+
+    let mut vm = VirtualMachine::new(code, 0);
+    assert_eq!(vm.step().unwrap(), Step::Executed(0, Instruction::JMP(8)));
+    assert_eq!(vm.step().unwrap(), Step::Executed(8, Instruction::PUSH(Immediate::U8(7))));
+    assert_eq!(vm.instr_ptr(), 11);
+
+    vm.exec().unwrap();
+    assert_eq!(vm.stack(), [Immediate::U8(7)]);
+    assert_eq!(vm.registers()[0], Immediate::U8(0));
+}
+
+#[test]
+fn callr_targets_are_decoded_once_known() {
+    // LDI R1 u32$13, CALLR R1, HLT, then the LDI hiding a PUSH at 13
+    let mut code = vec![6, 1, 4, 13, 0, 0, 0, 28, 1, 1];
+    code.extend(HIDDEN);
+
+    let mut vm = VirtualMachine::new(code, 0);
+    vm.exec().unwrap();
+
+    // the hidden PUSH is followed by a HLT, so the CALLR never returns
+    assert_eq!(vm.stack(), [Immediate::U8(7)]);
+    assert_eq!(vm.call_stack(), [9]);
+}
+
+#[test]
+fn cut_off_instrs_fault_when_reached() {
+    let mut vm = VirtualMachine::new(vec![3, 0, 1, 6, 0, 4, 1], 0);
+    let err = vm.exec().unwrap_err();
+
+    assert_eq!((err.instr_ptr, err.kind), (3, VmErrorKind::TruncatedInstruction));
+    assert_eq!(vm.instr_ptr(), 3);
+    assert_eq!(vm.stack(), [Immediate::U8(1)]);
+}
+
+#[test]
+fn writes_to_watched_locations_pause_execution() {
+    // LDI R0 u8$7, CPY R0 R1, PUSHR R1, HSTORE 2, PUSH u8$3, POP R2, HLT
+    let code = vec![6, 0, 0, 7, 7, 0, 1, 4, 1, 23, 2, 0, 0, 0, 3, 0, 3, 5, 2, 1];
+
+    let mut vm = VirtualMachine::new(code, 4);
+    vm.add_watch(Watch::Register(1));
+    vm.add_watch(Watch::Heap(1..3));
+
+    assert_eq!(vm.exec().unwrap(), ExitStatus::Watchpoint);
+    assert_eq!(vm.instr_ptr(), 7);
+    assert_eq!(vm.exec().unwrap(), ExitStatus::Watchpoint);
+    assert_eq!(vm.take_watch_hits(), [
+        WatchHit { instr_ptr: 4, location: Location::Register(1), old: Immediate::U8(0), new: Immediate::U8(7) },
+        WatchHit { instr_ptr: 9, location: Location::Heap(2), old: Immediate::None(), new: Immediate::U8(7) },
+    ]);
+
+    // R2 isn't watched
+    assert_eq!(vm.exec().unwrap(), ExitStatus::Halted);
+    assert!(vm.take_watch_hits().is_empty());
+
+    assert!(vm.remove_watch(&Watch::Register(1)));
+    assert!(!vm.remove_watch(&Watch::Register(1)));
+    assert_eq!(vm.watches(), [Watch::Heap(1..3)]);
+}
+
+#[test]
+fn registers_past_r15_fault_instead_of_panicking() {
+    let err = VirtualMachine::new(vec![4, 200], 0).exec().unwrap_err();
+    assert_eq!((err.instr_ptr, err.opcode, err.kind), (0, 4, VmErrorKind::InvalidRegister(200)));
+
+    // CPY R0 R16, after a NOP
+    let mut vm = VirtualMachine::new(vec![0, 7, 0, 16, 1], 0);
+    let err = vm.exec().unwrap_err();
+    assert_eq!((err.instr_ptr, err.opcode, err.kind), (1, 7, VmErrorKind::InvalidRegister(16)));
+    assert_eq!(vm.next_instr(), None);
+}
+
+#[test]
+fn verified_calls_into_the_middle_of_an_instr_fault() {
+    // the CALLR lands on the immediate's 200, read as PUSHR R200
+    let program = Assembler::new("@ R0 u8$8\ncallR R0\n$ u16$51204\n".to_string()).assemble().unwrap();
+    assert_eq!(verify(&program.code, program.entry), Ok(()));
+
+    let err = VirtualMachine::from_program(program, 0).exec().unwrap_err();
+    assert_eq!((err.instr_ptr, err.opcode, err.kind), (8, 4, VmErrorKind::InvalidRegister(200)));
+}
+
+#[test]
+fn heap_allocs_past_the_heap_limit_fault() {
+    let alloc = |size: &str, limit: Option<usize>| {
+        let program = Assembler::new(format!("$ {size}\nint 1\n")).assemble().unwrap();
+        let mut vm = VirtualMachine::from_program(program, 2);
+
+        if let Some(limit) = limit {
+            vm.set_heap_limit(limit);
+        }
+
+        vm.exec().map(|_| vm.stack().to_vec()).map_err(|e| (e.instr_ptr, e.opcode, e.kind))
+    };
+
+    assert_eq!(alloc("u64$18446744073709551615", None), Err((10, 2, VmErrorKind::HeapExhausted(usize::MAX))));
+    assert_eq!(alloc("u8$4", Some(3)), Err((3, 2, VmErrorKind::HeapExhausted(4))));
+    assert_eq!(alloc("u8$0", None), Err((3, 2, VmErrorKind::ZeroSizedAlloc)));
+
+    // a free region that's already there doesn't grow the heap, & free cells at its end are grown from
+    assert_eq!(alloc("u8$2", Some(2)), Ok(vec![Immediate::U64(0)]));
+    assert_eq!(alloc("u8$4", Some(4)), Ok(vec![Immediate::U64(0)]));
+}
+
+#[test]
+fn allocs_that_dont_fit_grow_from_the_free_cells_at_the_end_of_the_heap() {
+    let program = Assembler::new(".x .u8 1\n$ u8$3\nint 1\n".to_string()).assemble().unwrap();
+    let mut vm = VirtualMachine::from_program(program, 3);
+    vm.set_heap_limit(4);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.stack(), [Immediate::U64(1)]);
+    assert_eq!(vm.heap(), [Immediate::U8(1), Immediate::U8(0), Immediate::U8(0), Immediate::U8(0)]);
+}
+
+#[test]
+fn host_interrupts_can_be_registered_on_new_numbers() {
+    let mut vm = vm("$ u8$20\nint 1000\n");
+    vm.register_interrupt(1000, |ctx: &mut InterruptContext| {
+        let n = ctx.pop_addr()?;
+        ctx.push(Immediate::U64(n as u64 * 2));
+        Ok(())
+    });
+
+    vm.exec().unwrap();
+    assert_eq!(vm.stack(), [Immediate::U64(40)]);
+}
+
+#[test]
+fn removed_interrupts_fault() {
+    let mut vm = vm("$ u8$1\nint 1\n");
+    assert!(vm.remove_interrupt(INT_HEAP_ALLOC).is_some());
+    assert!(vm.remove_interrupt(INT_HEAP_ALLOC).is_none());
+
+    let err = vm.exec().unwrap_err();
+    assert_eq!((err.instr_ptr, err.opcode, err.kind), (3, 2, VmErrorKind::UnknownInterrupt(INT_HEAP_ALLOC)));
+}
+
+// counts R0 up to 3, executing 16 instrs in total
+const COUNT_TO_3: &str = "@ R0 u8$0\n@ R1 u8$1\n@ R2 u8$3\n.loop\n+ R0 R1\n% R0\n= R0 R2\n/! loop\n";
+
+#[test]
+fn running_out_of_fuel_stops_infinite_loops() {
+    let mut vm = vm(".loop\n// loop\n");
+    vm.set_fuel(Some(100));
+
+    assert_eq!(vm.exec().unwrap(), ExitStatus::OutOfFuel);
+    assert_eq!(vm.fuel(), Some(0));
+    assert_eq!(vm.instr_ptr(), 0);
+}
+
+#[test]
+fn adding_fuel_resumes_the_run() {
+    let mut vm = vm(COUNT_TO_3);
+    vm.set_fuel(Some(10));
+
+    assert_eq!(vm.exec().unwrap(), ExitStatus::OutOfFuel);
+    assert_eq!(vm.registers()[0], Immediate::U8(2));
+
+    vm.add_fuel(10);
+    assert_eq!(vm.exec().unwrap(), ExitStatus::Halted);
+    assert_eq!(vm.registers()[0], Immediate::U8(3));
+    assert_eq!(vm.fuel(), Some(4));
+}
+
+#[test]
+fn passed_deadlines_stop_the_run_before_anything_is_executed() {
+    let mut vm = vm(".loop\n// loop\n");
+    vm.set_deadline(Some(Instant::now()));
+
+    assert_eq!(vm.exec().unwrap(), ExitStatus::DeadlineExceeded);
+    assert_eq!(vm.instr_ptr(), 0);
+
+    // clearing the deadline resumes it
+    vm.set_deadline(None);
+    vm.set_fuel(Some(3));
+    assert_eq!(vm.exec().unwrap(), ExitStatus::OutOfFuel);
+}
+
+#[test]
+fn recursing_past_the_max_call_depth_faults() {
+    let mut vm = vm(".f\ncall f\n");
+    vm.set_max_call_depth(3);
+
+    let err = vm.exec().unwrap_err();
+    assert_eq!((err.instr_ptr, err.opcode, err.kind), (0, 27, VmErrorKind::CallStackOverflow));
+    assert_eq!(vm.call_stack(), [5, 5, 5]);
+}
+
+#[test]
+fn returning_without_a_call_faults() {
+    let err = vm("_\nret\n").exec().unwrap_err();
+    assert_eq!((err.instr_ptr, err.opcode, err.kind), (1, 29, VmErrorKind::CallStackUnderflow));
+}
+
+#[test]
+fn run_for_pauses_after_n_instrs() {
+    let mut vm = vm("_\n_\n_\n_\n");
+
+    assert_eq!(vm.run_for(3).unwrap(), ExitStatus::Paused);
+    assert_eq!(vm.instr_ptr(), 3);
+    assert_eq!(vm.run_for(0).unwrap(), ExitStatus::Paused);
+    assert_eq!(vm.instr_ptr(), 3);
+
+    assert_eq!(vm.run_for(10).unwrap(), ExitStatus::Halted);
+    assert_eq!(vm.instr_ptr(), 5);
+}
+
+#[test]
+fn run_until_checks_its_predicate_before_every_instr() {
+    let mut vm = vm("_\n_\n_\n_\n");
+
+    assert_eq!(vm.run_until(|_| true).unwrap(), ExitStatus::Paused);
+    assert_eq!(vm.instr_ptr(), 0);
+
+    let mut seen = vec![];
+    let status = vm.run_until(|vm| {
+        seen.push(vm.instr_ptr());
+        vm.instr_ptr() == 2
+    });
+
+    assert_eq!(status.unwrap(), ExitStatus::Paused);
+    assert_eq!(seen, [0, 1, 2]);
+}
+
+#[test]
+fn stepping_a_halted_program_does_nothing() {
+    let mut vm = vm("_\n");
+
+    assert_eq!(vm.step().unwrap(), Step::Executed(0, Instruction::NOP()));
+    assert_eq!(vm.step().unwrap(), Step::Executed(1, Instruction::HLT()));
+    assert_eq!(vm.step().unwrap(), Step::Halted);
+    assert_eq!(vm.step().unwrap(), Step::Halted);
+    assert_eq!(vm.run_for(1).unwrap(), ExitStatus::Halted);
+}
+
+#[test]
+fn every_fault_names_the_faulting_instr() {
+    let fault = |src: &str| {
+        let mut vm = vm(src);
+        vm.set_stderr(Box::new(io::sink()));
+
+        let err = vm.exec().unwrap_err();
+        (err.instr_ptr, err.opcode, err.kind)
+    };
+
+    assert_eq!(fault("@ R0 u8$1\n@ R1 i8$1\n+ R0 R1\n"), (8, 14, VmErrorKind::TypeMismatch("add two registers")));
+    assert_eq!(fault("@ R0 u8$1\n@ R1 u8$0\n/ R0 R1\n"), (8, 17, VmErrorKind::DivideByZero));
+    assert_eq!(fault("@ R0 u8$1\n> R0 u8$8\n"), (4, 21, VmErrorKind::ShiftOverflow));
+    assert_eq!(fault("@ R0 f32$1.5\nldR R0\n"), (7, 26, VmErrorKind::InvalidAddress(Immediate::F32(1.5))));
+    assert_eq!(fault("_\nld 5\n"), (1, 25, VmErrorKind::HeapOutOfBounds(5)));
+    assert_eq!(fault("int 99\n"), (0, 2, VmErrorKind::UnknownInterrupt(99)));
+    assert_eq!(fault(".msg .string \"oops\"\n$ u64$msg\nint 4\n"), (10, 2, VmErrorKind::Panic("oops".to_string())));
+}
+
+#[test]
+fn jumps_past_byte_255_land_where_they_should() {
+    let src = format!("// far\n{}.far\n@ R0 u8$7\n", "_\n".repeat(300));
+    let mut vm = vm(&src);
+
+    assert_eq!(vm.step().unwrap(), Step::Executed(0, Instruction::JMP(305)));
+    vm.exec().unwrap();
+
+    assert_eq!(vm.registers()[0], Immediate::U8(7));
+    assert_eq!(vm.instr_ptr(), 310);
+}
+
+#[test]
+fn static_heap_addresses_past_255_are_kept_whole() {
+    let program = Assembler::new("$ u8$9\nstr 300\nld 300\n".to_string()).assemble().unwrap();
+    let mut vm = VirtualMachine::from_program(program, 301);
+    vm.exec().unwrap();
+
+    assert_eq!(vm.heap()[300], Immediate::U8(9));
+    assert_eq!(vm.heap()[300 % 256], Immediate::None());
+    assert_eq!(vm.stack(), [Immediate::U8(9)]);
+}